@@ -0,0 +1,235 @@
+/// Methods of the contract with their purposes, returned by `interface`.
+pub const INTERFACE: &[(&str, &str)] = &[
+    ("init", "initializes the client from the first header"),
+    (
+        "init_and_add",
+        "initializes the client with the default thresholds",
+    ),
+    (
+        "init_with_checkpoint",
+        "initializes the client from an exported checkpoint",
+    ),
+    ("migrate", "migrates the state after an upgrade"),
+    ("initialized", "whether the client is initialized"),
+    ("interface", "names and purposes of the methods"),
+    ("keccak256", "keccak256 of the data"),
+    ("storage_prefix", "prefix of the storage keys"),
+    (
+        "trust_root",
+        "hash of the header the client was initialized with",
+    ),
+    ("state_version", "version of the state layout"),
+    ("dag_merkle_root", "DAG merkle root of an epoch"),
+    ("dag_merkle_roots", "DAG merkle roots of a range of epochs"),
+    ("dag_roots_commitment", "hash of all DAG merkle roots"),
+    (
+        "required_dag_nodes",
+        "number of DAG nodes to submit with a header",
+    ),
+    ("is_paused", "whether adding headers is suspended"),
+    ("dag_config", "first DAG epoch and the epoch length"),
+    (
+        "needs_dag_root_for_block",
+        "whether the epoch of a block has no DAG merkle root",
+    ),
+    (
+        "epoch_complete",
+        "whether the retained part of an epoch is complete",
+    ),
+    (
+        "canonical_count_in_epoch",
+        "canonical blocks retained in an epoch",
+    ),
+    (
+        "ready_for_next_epoch",
+        "whether the next epoch has a DAG merkle root",
+    ),
+    ("epochs_in_use", "epochs of the canonical blocks"),
+    (
+        "epochs_remaining",
+        "epochs left covered by the DAG merkle roots",
+    ),
+    ("recommended_gas", "gas to attach to add_block_header"),
+    ("last_block_number", "number of the best header"),
+    (
+        "finality_horizon",
+        "highest block number eligible for the GC of headers",
+    ),
+    (
+        "blocks_behind",
+        "how far the best header is behind a remote tip",
+    ),
+    ("epoch_submission_count", "headers recorded in an epoch"),
+    ("recent_reorgs", "the most recent reorgs"),
+    (
+        "last_was_reorg",
+        "whether the last submission caused a reorg",
+    ),
+    ("pow_verifications", "headers whose PoW was verified"),
+    ("is_retained", "whether a canonical block is still stored"),
+    (
+        "earliest_available_block",
+        "lowest number on the canonical chain",
+    ),
+    (
+        "export_checkpoint",
+        "checkpoint of the best header and the config",
+    ),
+    ("stats", "summary of the client state"),
+    ("block_hash", "canonical hash at a number"),
+    ("get_headers", "headers with the given hashes"),
+    ("tip_header", "best header"),
+    ("canonical_header", "canonical header at a number"),
+    ("verify_block", "whether a hash is canonical at a number"),
+    ("extends_tip", "whether headers extend the best header"),
+    ("matches_canonical", "whether a header is canonical"),
+    (
+        "finalized_at_timestamp",
+        "final canonical hash at a timestamp",
+    ),
+    (
+        "canonical_at_or_below",
+        "highest canonical block not above a number",
+    ),
+    ("number_of", "number of the header with a hash"),
+    (
+        "header_status",
+        "whether a header is unknown, known or canonical",
+    ),
+    (
+        "is_pow_verified",
+        "whether the PoW of a header was verified",
+    ),
+    (
+        "header_number_difficulty",
+        "number and difficulty of a header",
+    ),
+    (
+        "header_roots",
+        "state, transactions and receipts roots of a header",
+    ),
+    ("header_seal", "nonce and mix hash of a header"),
+    ("header_logs_bloom", "logs bloom of a header"),
+    ("verify_and_extract", "verifies a header without storing it"),
+    (
+        "verify_checkpoint_link",
+        "whether a PoW chain links a checkpoint to the best header",
+    ),
+    ("reverify", "verifies a stored header again"),
+    (
+        "canonical_parent_ok",
+        "whether a canonical header links to its parent",
+    ),
+    ("known_hashes", "all hashes known at a number"),
+    (
+        "recent_canonical",
+        "canonical hashes from the best header down",
+    ),
+    ("fork_counts", "known headers per number"),
+    ("block_hash_safe", "canonical hash at a number if final"),
+    ("safe_block_number", "highest final number"),
+    (
+        "is_finalized_block",
+        "whether a block is final and canonical",
+    ),
+    ("is_stalled", "whether the client fell behind"),
+    (
+        "prospective_total_difficulty",
+        "total difficulty of a prospective child",
+    ),
+    (
+        "confirm_total_difficulty",
+        "whether a header has a total difficulty",
+    ),
+    (
+        "recompute_total_difficulty",
+        "total difficulty from the stored headers",
+    ),
+    ("is_ancestor", "whether a header is an ancestor of another"),
+    ("dag_access_indices", "DAG indices accessed by a header"),
+    (
+        "check_proof_ordering",
+        "whether the DAG nodes are in the access order",
+    ),
+    ("attach_pow_proof", "verifies the PoW of a trusted header"),
+    ("next_gc_target", "headers removed by the next GC step"),
+    ("gc_preview", "headers removed by prune_below"),
+    (
+        "gc_impact_of",
+        "numbers removed by the GC for a new best header",
+    ),
+    ("prune_fork", "removes the headers of an abandoned fork"),
+    ("prune_below", "removes old headers ahead of the GC"),
+    ("run_gc", "finishes the GC for the best header"),
+    ("set_max_gc_per_call", "sets the GC limit per call"),
+    (
+        "set_tie_break",
+        "sets the tie break between equal total difficulties",
+    ),
+    ("set_max_extra_data_bytes", "sets the extra data limit"),
+    ("set_max_header_gap", "sets how far ahead a header can be"),
+    ("set_consensus", "sets how seals are verified"),
+    (
+        "set_on_missing_parent",
+        "sets what happens to orphan headers",
+    ),
+    (
+        "set_difficulty_tolerance_permille",
+        "sets the difficulty tolerance",
+    ),
+    (
+        "set_min_submission_deposit",
+        "sets the deposit required to submit a header",
+    ),
+    (
+        "set_initial_total_difficulty",
+        "sets the total difficulty of the first header",
+    ),
+    (
+        "set_min_header_timestamp",
+        "sets the minimum timestamp of new headers",
+    ),
+    (
+        "set_store_full_headers",
+        "sets whether headers are stored in full",
+    ),
+    (
+        "set_require_empty_uncles",
+        "sets whether headers with uncles are rejected",
+    ),
+    (
+        "set_max_orphan_reprocess_per_call",
+        "sets how many orphans a call records",
+    ),
+    ("set_finality_mode", "sets when blocks are final"),
+    (
+        "set_dag_start_epoch",
+        "corrects the epoch of the first DAG merkle root",
+    ),
+    ("pause", "suspends adding headers"),
+    ("unpause", "resumes adding headers"),
+    (
+        "replace_dag_roots",
+        "replaces the DAG merkle roots while paused",
+    ),
+    ("clear_orphans", "drops the buffered orphan headers"),
+    (
+        "process_pending_orphans",
+        "records the buffered headers left by the previous calls",
+    ),
+    ("add_relayer", "allows a relayer"),
+    ("remove_relayer", "disallows a relayer"),
+    ("add_block_header", "adds a header"),
+    (
+        "add_block_header_checked",
+        "adds a header with the expected hash",
+    ),
+    (
+        "add_block_header_result",
+        "adds a header, returning a status code",
+    ),
+    (
+        "add_block_header_compact",
+        "adds a header with packed DAG nodes",
+    ),
+];
@@ -1,17 +1,112 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use eth_types::*;
-use near_sdk::collections::UnorderedMap;
-use near_sdk::AccountId;
-use near_sdk::{env, near_bindgen};
+use near_sdk::collections::{UnorderedMap, UnorderedSet};
+use near_sdk::{env, near_bindgen, Promise};
+use near_sdk::{AccountId, Balance};
 
 #[cfg(target_arch = "wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+mod interface;
+
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod tests;
 
+use interface::INTERFACE;
+
+/// Number of blocks in one ethash epoch, i.e. how often the DAG is regenerated.
+pub const EPOCH_LENGTH: u64 = 30000;
+
+/// Number of ethash accesses to the DAG for every header. Each access reads one pair of DAG
+/// nodes.
+const ETHASH_ACCESSES: u64 = 64;
+
+/// Maximum number of items returned by a single view call.
+const MAX_QUERY_LENGTH: u64 = 1000;
+
+/// Default `hashes_gc_threshold` of `init_and_add`, roughly 7 days of Ethereum blocks.
+const DEFAULT_HASHES_GC_THRESHOLD: u64 = 40_000;
+
+/// Default `finalized_gc_threshold` of `init_and_add`.
+const DEFAULT_FINALIZED_GC_THRESHOLD: u64 = 500;
+
+/// Default number of confirmations of `init_and_add`.
+const DEFAULT_NUM_CONFIRMATIONS: u64 = 10;
+
+/// Default number of block numbers removed from each collection by a single GC pass.
+const DEFAULT_MAX_GC_PER_CALL: u64 = 1000;
+
+/// Maximum length of the header extra data on Ethereum mainnet.
+const DEFAULT_MAX_EXTRA_DATA_BYTES: u64 = 32;
+
+/// Maximum number of buffered headers with unknown parents.
+const MAX_ORPHANS: u64 = 16;
+
+/// Number of the most recent reorgs kept by `recent_reorgs`.
+const MAX_REORG_EVENTS: usize = 32;
+
+/// Length of the signature at the end of the extra data of clique headers.
+const CLIQUE_SEAL_LENGTH: usize = 65;
+
+/// Default allowed deviation of the ethash difficulty from the expected one, in thousandths.
+const DEFAULT_DIFFICULTY_TOLERANCE_PERMILLE: u16 = 10;
+
+// Status codes returned by `add_block_header_result`.
+
+/// The header was recorded.
+pub const ADD_HEADER_OK: u8 = 0;
+/// The parent of the header is unknown and the client doesn't buffer orphans.
+pub const ADD_HEADER_UNKNOWN_PARENT: u8 = 1;
+/// The header is already known.
+pub const ADD_HEADER_KNOWN: u8 = 2;
+/// The header is too old to appear on the canonical chain.
+pub const ADD_HEADER_STALE: u8 = 3;
+/// The header is invalid, e.g. its PoW doesn't match the DAG nodes.
+pub const ADD_HEADER_INVALID: u8 = 4;
+
+/// Why a submitted header is not added: one of the `ADD_HEADER_*` status codes and the message
+/// `add_block_header` panics with.
+struct Rejection {
+    code: u8,
+    message: String,
+}
+
+impl Rejection {
+    fn new(code: u8, message: String) -> Self {
+        Self { code, message }
+    }
+}
+
+/// Minimal difficulty of ethash headers.
+const MIN_DIFFICULTY: u64 = 131_072;
+
+/// Mainnet block number of Homestead, which changed the difficulty adjustment (EIP-2).
+const HOMESTEAD_BLOCK: u64 = 1_150_000;
+
+/// Mainnet block number of Byzantium, which made the difficulty adjustment account for the uncles
+/// of the parent (EIP-100).
+const BYZANTIUM_BLOCK: u64 = 4_370_000;
+
+/// Difficulty bomb delays on mainnet as pairs of the fork block number and the delay in blocks,
+/// latest fork first. The expected difficulty is checked whenever ethash is validated, whatever
+/// the chain, and other chains activate the delays at other blocks, so only mainnet is supported.
+const BOMB_DELAYS: [(u64, u64); 6] = [
+    (15_050_000, 11_400_000), // Gray Glacier, EIP-5133
+    (13_773_000, 10_700_000), // Arrow Glacier, EIP-4345
+    (12_965_000, 9_700_000),  // London, EIP-3554
+    (9_200_000, 9_000_000),   // Muir Glacier, EIP-2384
+    (7_280_000, 5_000_000),   // Constantinople, EIP-1234
+    (4_370_000, 3_000_000),   // Byzantium, EIP-649
+];
+
+/// Estimated gas spent by `add_block_header` independently of the DAG size.
+const BASE_ADD_HEADER_GAS: u64 = 10_000_000_000_000;
+
+/// Estimated gas spent by `add_block_header` per level of the DAG merkle proofs.
+const ADD_HEADER_GAS_PER_PROOF_LEVEL: u64 = 2_000_000_000_000;
+
 #[derive(Default, Debug, Clone, BorshDeserialize, BorshSerialize)]
 pub struct DoubleNodeWithMerkleProof {
     pub dag_nodes: Vec<H512>, // [H512; 2]
@@ -32,12 +127,17 @@ impl DoubleNodeWithMerkleProof {
         Self::truncate_to_h128(near_sha256(&data).into())
     }
 
+    /// Returns the merkle leaf of the two concatenated 64-byte DAG nodes.
+    pub fn sha256_merkle_leaf(dag_node_pair: &[u8]) -> H128 {
+        Self::truncate_to_h128(near_sha256(dag_node_pair).into())
+    }
+
     pub fn apply_merkle_proof(&self, index: u64) -> H128 {
         let mut data = [0u8; 128];
         data[..64].copy_from_slice(&(self.dag_nodes[0].0).0);
         data[64..].copy_from_slice(&(self.dag_nodes[1].0).0);
 
-        let mut leaf = Self::truncate_to_h128(near_sha256(&data).into());
+        let mut leaf = Self::sha256_merkle_leaf(&data);
 
         for i in 0..self.proof.len() {
             if (index >> i as u64) % 2 == 0 {
@@ -48,6 +148,109 @@ impl DoubleNodeWithMerkleProof {
         }
         leaf
     }
+
+    /// Packs the nodes into the layout read by `unpack`. All proofs must have the same length.
+    pub fn pack(nodes: &[DoubleNodeWithMerkleProof]) -> Vec<u8> {
+        let proof_length = nodes.first().map_or(0, |node| node.proof.len());
+        let mut packed = vec![proof_length as u8];
+        for node in nodes {
+            assert_eq!(node.proof.len(), proof_length, "Proofs differ in length");
+            for dag_node in &node.dag_nodes {
+                packed.extend_from_slice(&(dag_node.0).0);
+            }
+            for item in &node.proof {
+                packed.extend_from_slice(&(item.0).0);
+            }
+        }
+        packed
+    }
+
+    /// Unpacks the nodes from the compact layout used by `add_block_header_compact`: the shared
+    /// proof length `K` as one byte, followed by the nodes, each as 128 bytes of the two DAG nodes
+    /// and `K` 16-byte proof items.
+    pub fn unpack(packed: &[u8]) -> Vec<DoubleNodeWithMerkleProof> {
+        let (proof_length, packed) = packed.split_first().expect("Packed DAG nodes are empty");
+        let node_length = 128 + 16 * *proof_length as usize;
+        assert_eq!(
+            packed.len() % node_length,
+            0,
+            "Packed DAG nodes do not match the proof length"
+        );
+        packed
+            .chunks(node_length)
+            .map(|chunk| {
+                let (dag_nodes, proof) = chunk.split_at(128);
+                DoubleNodeWithMerkleProof {
+                    dag_nodes: dag_nodes
+                        .chunks(64)
+                        .map(|dag_node| {
+                            let mut data = [0u8; 64];
+                            data.copy_from_slice(dag_node);
+                            H512(data.into())
+                        })
+                        .collect(),
+                    proof: proof
+                        .chunks(16)
+                        .map(|item| {
+                            let mut data = [0u8; 16];
+                            data.copy_from_slice(item);
+                            H128(data.into())
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// DAG nodes whose merkle proofs were verified during a single hashimoto run. A node accessed
+/// several times only has its proof verified once.
+struct VerifiedNodes<'a> {
+    merkle_root: H128,
+    /// DAG nodes by their index in the DAG, borrowed from the submitted ones.
+    nodes: std::collections::BTreeMap<u64, &'a [H512]>,
+    /// Number of merkle proofs verified so far.
+    #[cfg(test)]
+    proofs_applied: u64,
+}
+
+impl<'a> VerifiedNodes<'a> {
+    fn new(merkle_root: H128) -> Self {
+        Self {
+            merkle_root,
+            nodes: Default::default(),
+            #[cfg(test)]
+            proofs_applied: 0,
+        }
+    }
+
+    /// Fails unless the node is at the given index of the DAG. `position` is the position of the
+    /// node among the submitted ones, reported when its proof does not lead to the merkle root.
+    fn verify(
+        &mut self,
+        position: usize,
+        index: u64,
+        node: &'a DoubleNodeWithMerkleProof,
+    ) -> Result<(), String> {
+        match self.nodes.get(&index) {
+            Some(dag_nodes) if *dag_nodes == &node.dag_nodes[..] => Ok(()),
+            Some(_) => Err(format!(
+                "dag node {} differs from the one accessed before",
+                position
+            )),
+            None => {
+                if self.merkle_root != node.apply_merkle_proof(index) {
+                    return Err(format!("dag proof epoch mismatch at node {}", position));
+                }
+                #[cfg(test)]
+                {
+                    self.proofs_applied += 1;
+                }
+                self.nodes.insert(index, &node.dag_nodes);
+                Ok(())
+            }
+        }
+    }
 }
 
 /// Minimal information about a header.
@@ -56,6 +259,164 @@ pub struct HeaderInfo {
     pub total_difficulty: U256,
     pub parent_hash: H256,
     pub number: u64,
+    /// Difficulty of the header itself.
+    pub difficulty: U256,
+    /// Timestamp of the header, in seconds.
+    pub timestamp: u64,
+    /// Whether the PoW of the header, or its seal in the clique mode, was verified.
+    pub pow_verified: bool,
+}
+
+/// A header stored without the fields that are needed neither to verify its children nor to
+/// serve its roots: the author, the logs bloom and the extra data.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CompactHeader {
+    pub parent_hash: H256,
+    pub uncles_hash: H256,
+    pub state_root: H256,
+    pub transactions_root: H256,
+    pub receipts_root: H256,
+    pub difficulty: U256,
+    pub number: u64,
+    pub gas_limit: U256,
+    pub gas_used: U256,
+    pub timestamp: u64,
+    pub mix_hash: H256,
+    pub nonce: H64,
+    pub hash: H256,
+    pub partial_hash: H256,
+}
+
+impl CompactHeader {
+    pub fn from_header(header: &BlockHeader) -> Self {
+        Self {
+            parent_hash: header.parent_hash,
+            uncles_hash: header.uncles_hash,
+            state_root: header.state_root,
+            transactions_root: header.transactions_root,
+            receipts_root: header.receipts_root,
+            difficulty: header.difficulty,
+            number: header.number,
+            gas_limit: header.gas_limit,
+            gas_used: header.gas_used,
+            timestamp: header.timestamp,
+            mix_hash: header.mix_hash,
+            nonce: header.nonce,
+            hash: header.hash.unwrap(),
+            partial_hash: header.partial_hash.unwrap(),
+        }
+    }
+
+    /// Returns the header with the dropped fields left empty. Its RLP doesn't hash to its hash.
+    pub fn into_header(self) -> BlockHeader {
+        BlockHeader {
+            parent_hash: self.parent_hash,
+            uncles_hash: self.uncles_hash,
+            author: Default::default(),
+            state_root: self.state_root,
+            transactions_root: self.transactions_root,
+            receipts_root: self.receipts_root,
+            log_bloom: Default::default(),
+            difficulty: self.difficulty,
+            number: self.number,
+            gas_limit: self.gas_limit,
+            gas_used: self.gas_used,
+            timestamp: self.timestamp,
+            extra_data: vec![],
+            mix_hash: self.mix_hash,
+            nonce: self.nonce,
+            hash: Some(self.hash),
+            partial_hash: Some(self.partial_hash),
+        }
+    }
+}
+
+/// How the canonical chain is chosen between headers with equal total difficulty.
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum TieBreak {
+    /// Switch to the new header if its difficulty is even.
+    EvenDifficulty,
+    /// Switch to the new header if it has a higher number. On equal numbers the header that was
+    /// seen first stays.
+    HigherNumber,
+}
+
+/// How the seal of the headers is verified.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum ConsensusMode {
+    /// Ethash PoW, verified against the DAG merkle roots.
+    Ethash,
+    /// Clique PoA, where the last 65 bytes of the extra data are the signature of one of the
+    /// `signers`. The signature counts towards `max_extra_data_bytes`.
+    Clique { signers: Vec<Address> },
+}
+
+/// What happens to a new header whose parent is unknown.
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum OnMissingParent {
+    /// Reject the header.
+    Reject,
+    /// Buffer the header and add it once its parent is added. The seal of the header is verified
+    /// when it is buffered, the header is dropped if it is invalid against its parent.
+    Buffer,
+}
+
+/// When a canonical block is considered final.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum FinalityMode {
+    /// The block has at least the given number of confirmations.
+    FixedDepth(u64),
+    /// The total difficulty of the best header exceeds the total difficulty of the block by at
+    /// least the given amount.
+    Difficulty(U256),
+}
+
+/// Summary of the client state for monitoring.
+#[derive(Default, Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct BridgeStats {
+    pub headers_count: u64,
+    pub canonical_count: u64,
+    pub reorg_count: u64,
+    pub earliest_available_block: u64,
+}
+
+/// A change of the canonical chain other than its extension.
+#[derive(Debug, Clone, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct ReorgEvent {
+    /// Number of the common ancestor of the old and the new best headers.
+    pub at_block: u64,
+    pub old_tip: H256,
+    pub new_tip: H256,
+    /// Number of canonical blocks of the old chain that were replaced or dropped.
+    pub depth: u64,
+}
+
+/// The minimal state to bootstrap a new client from the best header of an existing one.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Checkpoint {
+    pub best_header_hash: H256,
+    pub best_header_info: HeaderInfo,
+    /// RLP-encoded best header.
+    pub best_header: Vec<u8>,
+    pub validate_ethash: bool,
+    pub dags_start_epoch: u64,
+    pub dags_merkle_roots: Vec<H128>,
+    pub hashes_gc_threshold: u64,
+    pub finalized_gc_threshold: u64,
+    pub finality_mode: FinalityMode,
+    pub trusted_signer: Option<AccountId>,
+    pub max_gc_per_call: u64,
+    pub tie_break: TieBreak,
+    pub max_extra_data_bytes: u64,
+    pub max_header_gap: u64,
+    pub consensus: ConsensusMode,
+    pub on_missing_parent: OnMissingParent,
+    pub difficulty_tolerance_permille: u16,
+    pub require_empty_uncles: bool,
+    pub max_orphan_reprocess_per_call: u64,
+    pub min_submission_deposit: Balance,
+    pub store_full_headers: bool,
+    pub min_header_timestamp: u64,
 }
 
 #[near_bindgen]
@@ -68,6 +429,9 @@ pub struct EthClient {
     dags_start_epoch: u64,
     /// DAG merkle roots for the next several years.
     dags_merkle_roots: Vec<H128>,
+    /// Keccak256 of the concatenated DAG merkle roots. The roots can only be replaced while the
+    /// client is paused, so this commitment can be audited against an independent copy of them.
+    dag_roots_hash: H256,
     /// Hash of the header that has the highest cumulative difficulty. The current head of the
     /// canonical chain.
     best_header_hash: H256,
@@ -80,9 +444,9 @@ pub struct EthClient {
     /// This is required to be able to adjust the canonical chain when the fork switch happens.
     /// The commonly used number is 500 blocks, so this number should be 500 in production.
     finalized_gc_threshold: u64,
-    /// Number of confirmations that applications can use to consider the transaction safe.
-    /// For most use cases 25 should be enough, for super safe cases it should be 500.
-    num_confirmations: u64,
+    /// When applications can consider the transaction safe. Initialized with a fixed number of
+    /// confirmations, for most use cases 25 should be enough, for super safe cases it should be 500.
+    finality_mode: FinalityMode,
     /// Hashes of the canonical chain mapped to their numbers. Stores up to `hashes_gc_threshold`
     /// entries.
     /// header number -> header hash
@@ -92,12 +456,89 @@ pub struct EthClient {
     all_header_hashes: UnorderedMap<u64, Vec<H256>>,
     /// Known headers. Stores up to `finalized_gc_threshold`.
     headers: UnorderedMap<H256, BlockHeader>,
+    /// Headers recorded while `store_full_headers` is false. Each header is stored either here or
+    /// in `headers`.
+    compact_headers: UnorderedMap<H256, CompactHeader>,
     /// Minimal information about the headers, like cumulative difficulty. Stores up to
     /// `finalized_gc_threshold`.
     infos: UnorderedMap<H256, HeaderInfo>,
     /// If set, block header added by trusted signer will skip validation and added by
     /// others will be immediately rejected, used in PoA testnets
     trusted_signer: Option<AccountId>,
+    /// Accounts allowed to submit headers. If empty, anyone can submit headers.
+    relayers: UnorderedSet<AccountId>,
+    /// Number of times the canonical chain switched to a header that does not extend the previous
+    /// best header.
+    reorg_count: u64,
+    /// Maximum number of block numbers removed from each collection by a single GC pass, which
+    /// bounds the gas spent on GC by a call.
+    max_gc_per_call: u64,
+    /// How the canonical chain is chosen between headers with equal total difficulty.
+    tie_break: TieBreak,
+    /// NEAR block timestamp of the last recorded header, in nanoseconds.
+    last_update_block_timestamp: u64,
+    /// Maximum length of the extra data of the accepted headers.
+    max_extra_data_bytes: u64,
+    /// Maximum number of blocks by which a new header can be ahead of the best header.
+    max_header_gap: u64,
+    /// How the seal of the headers is verified.
+    consensus: ConsensusMode,
+    /// What happens to a new header whose parent is unknown.
+    on_missing_parent: OnMissingParent,
+    /// Allowed deviation of the ethash difficulty from the one expected from the parent, in
+    /// thousandths of the expected difficulty.
+    difficulty_tolerance_permille: u16,
+    /// Whether only the headers without uncles are accepted. The uncles are not verified, so this
+    /// rejects the headers referencing uncles the client can't validate.
+    require_empty_uncles: bool,
+    /// Maximum number of buffered headers recorded by a single call once their parent is added.
+    max_orphan_reprocess_per_call: u64,
+    /// Deposit that has to be attached to every submission of a header. The deposit is kept by
+    /// the contract, which makes spamming a permissionless client costly.
+    min_submission_deposit: Balance,
+    /// Whether new headers are stored in full. Otherwise their logs bloom and extra data are
+    /// dropped, and the views returning the RLP of the header return `None` for them.
+    store_full_headers: bool,
+    /// Headers with a lower timestamp, in seconds, are rejected, which prevents backfilling
+    /// arbitrarily old blocks.
+    min_header_timestamp: u64,
+    /// Buffered headers with unknown parents. Stores up to `MAX_ORPHANS` entries, the one with the
+    /// lowest number is evicted to buffer another one.
+    /// header hash -> RLP-encoded header
+    orphans: UnorderedMap<H256, Vec<u8>>,
+    /// Number of recorded headers per epoch, not counting the first header.
+    /// epoch -> number of headers
+    epoch_submissions: UnorderedMap<u64, u64>,
+    /// Whether adding new headers is suspended.
+    paused: bool,
+    /// Version of the state layout, bumped by every `migrate`.
+    state_version: u32,
+    /// Number of submitted headers whose ethash PoW was verified, including buffered orphans.
+    pow_verifications: u64,
+    /// Prefix of the storage keys of all collections, set by `init`.
+    prefix: Vec<u8>,
+    /// Whether the last submission of a header changed the canonical chain other than by
+    /// extending it.
+    last_submit_was_reorg: bool,
+    /// The last `MAX_REORG_EVENTS` reorgs, from the oldest to the newest.
+    reorg_events: Vec<ReorgEvent>,
+    /// Hash of the header the client was initialized with: the first header passed to `init` or
+    /// the best header of the checkpoint passed to `init_with_checkpoint`.
+    trust_root: H256,
+    /// Lowest number on the canonical chain. The canonical chain is contiguous from it to the best
+    /// header, the numbers below it were removed by the GC.
+    lowest_canonical_number: u64,
+    /// No header information is stored for the numbers below this one. The numbers above it may
+    /// have no headers, e.g. after `prune_fork`.
+    lowest_header_number: u64,
+    /// The highest final canonical block number in the difficulty finality mode. Blocks only
+    /// become final as the total difficulty of the best header grows, so it is advanced whenever
+    /// the best header changes instead of being searched for.
+    difficulty_safe_number: Option<u64>,
+}
+
+fn assert_self() {
+    assert_eq!(env::current_account_id(), env::predecessor_account_id());
 }
 
 impl Default for EthClient {
@@ -108,6 +549,9 @@ impl Default for EthClient {
 
 #[near_bindgen]
 impl EthClient {
+    /// Ethereum headers do not commit to a chain id and the DAG is the same for every ethash
+    /// chain, so the client is bound to a chain only by `first_header`: every other header has to
+    /// descend from it.
     #[init]
     pub fn init(
         #[serializer(borsh)] validate_ethash: bool,
@@ -118,24 +562,56 @@ impl EthClient {
         #[serializer(borsh)] finalized_gc_threshold: u64,
         #[serializer(borsh)] num_confirmations: u64,
         #[serializer(borsh)] trusted_signer: Option<AccountId>,
+        #[serializer(borsh)] prefix: Vec<u8>,
     ) -> Self {
         assert!(!Self::initialized(), "Already initialized");
         let header: BlockHeader = rlp::decode(first_header.as_slice()).unwrap();
         let header_hash = header.hash.unwrap().clone();
         let header_number = header.number;
+        // Namespaces the collections, so that several clients can share the same storage.
+        let prefixed = |key: &[u8]| [prefix.as_slice(), key].concat();
         let mut res = Self {
             validate_ethash,
             dags_start_epoch,
+            dag_roots_hash: Self::hash_dag_roots(&dags_merkle_roots),
             dags_merkle_roots,
             best_header_hash: header_hash.clone(),
             hashes_gc_threshold,
             finalized_gc_threshold,
-            num_confirmations,
-            canonical_header_hashes: UnorderedMap::new(b"c".to_vec()),
-            all_header_hashes: UnorderedMap::new(b"a".to_vec()),
-            headers: UnorderedMap::new(b"h".to_vec()),
-            infos: UnorderedMap::new(b"i".to_vec()),
+            finality_mode: FinalityMode::FixedDepth(num_confirmations),
+            canonical_header_hashes: UnorderedMap::new(prefixed(b"c")),
+            all_header_hashes: UnorderedMap::new(prefixed(b"a")),
+            headers: UnorderedMap::new(prefixed(b"h")),
+            compact_headers: UnorderedMap::new(prefixed(b"k")),
+            infos: UnorderedMap::new(prefixed(b"i")),
             trusted_signer,
+            relayers: UnorderedSet::new(prefixed(b"r")),
+            reorg_count: 0,
+            max_gc_per_call: DEFAULT_MAX_GC_PER_CALL,
+            tie_break: TieBreak::EvenDifficulty,
+            last_update_block_timestamp: env::block_timestamp(),
+            max_extra_data_bytes: DEFAULT_MAX_EXTRA_DATA_BYTES,
+            max_header_gap: u64::max_value(),
+            consensus: ConsensusMode::Ethash,
+            on_missing_parent: OnMissingParent::Reject,
+            difficulty_tolerance_permille: DEFAULT_DIFFICULTY_TOLERANCE_PERMILLE,
+            require_empty_uncles: false,
+            max_orphan_reprocess_per_call: MAX_ORPHANS,
+            min_submission_deposit: 0,
+            store_full_headers: true,
+            min_header_timestamp: 0,
+            orphans: UnorderedMap::new(prefixed(b"o")),
+            epoch_submissions: UnorderedMap::new(prefixed(b"e")),
+            paused: false,
+            state_version: 1,
+            pow_verifications: 0,
+            prefix,
+            last_submit_was_reorg: false,
+            reorg_events: vec![],
+            trust_root: header_hash,
+            lowest_canonical_number: header_number,
+            lowest_header_number: header_number,
+            difficulty_safe_number: None,
         };
         res.canonical_header_hashes
             .insert(&header_number, &header_hash);
@@ -148,263 +624,2116 @@ impl EthClient {
                 total_difficulty: Default::default(),
                 parent_hash: Default::default(),
                 number: header_number,
+                difficulty: header.difficulty,
+                timestamp: header.timestamp,
+                pow_verified: false,
             },
         );
         res
     }
 
+    /// Initializes the client validating the PoW of every header after `first_header`, with the
+    /// production thresholds used by the relayer and no storage prefix. Like `init`, the first
+    /// header becomes the best header in the same call.
+    #[init]
+    pub fn init_and_add(
+        #[serializer(borsh)] dags_start_epoch: u64,
+        #[serializer(borsh)] dags_merkle_roots: Vec<H128>,
+        #[serializer(borsh)] first_header: Vec<u8>,
+    ) -> Self {
+        Self::init(
+            true,
+            dags_start_epoch,
+            dags_merkle_roots,
+            first_header,
+            DEFAULT_HASHES_GC_THRESHOLD,
+            DEFAULT_FINALIZED_GC_THRESHOLD,
+            DEFAULT_NUM_CONFIRMATIONS,
+            None,
+            vec![],
+        )
+    }
+
+    /// Initializes the client from a checkpoint exported by `export_checkpoint`. Unlike `init`,
+    /// the best header keeps its total difficulty. Only the best header is known to the new client,
+    /// so the canonical chain starts from it.
+    #[init]
+    pub fn init_with_checkpoint(
+        #[serializer(borsh)] checkpoint: Vec<u8>,
+        #[serializer(borsh)] prefix: Vec<u8>,
+    ) -> Self {
+        let checkpoint = Checkpoint::try_from_slice(&checkpoint).expect("Invalid checkpoint");
+        let mut res = Self::init(
+            checkpoint.validate_ethash,
+            checkpoint.dags_start_epoch,
+            checkpoint.dags_merkle_roots,
+            checkpoint.best_header,
+            checkpoint.hashes_gc_threshold,
+            checkpoint.finalized_gc_threshold,
+            0,
+            checkpoint.trusted_signer,
+            prefix,
+        );
+        assert_eq!(
+            res.best_header_hash, checkpoint.best_header_hash,
+            "Checkpoint header does not match its hash"
+        );
+        res.infos
+            .insert(&checkpoint.best_header_hash, &checkpoint.best_header_info);
+        res.max_gc_per_call = checkpoint.max_gc_per_call;
+        res.tie_break = checkpoint.tie_break;
+        res.max_extra_data_bytes = checkpoint.max_extra_data_bytes;
+        res.max_header_gap = checkpoint.max_header_gap;
+        res.consensus = checkpoint.consensus;
+        res.on_missing_parent = checkpoint.on_missing_parent;
+        res.finality_mode = checkpoint.finality_mode;
+        res.advance_difficulty_finality();
+        res.difficulty_tolerance_permille = checkpoint.difficulty_tolerance_permille;
+        res.require_empty_uncles = checkpoint.require_empty_uncles;
+        res.max_orphan_reprocess_per_call = checkpoint.max_orphan_reprocess_per_call;
+        res.min_submission_deposit = checkpoint.min_submission_deposit;
+        res.store_full_headers = checkpoint.store_full_headers;
+        res.min_header_timestamp = checkpoint.min_header_timestamp;
+        res
+    }
+
+    /// Migrates the state after the contract code is upgraded and bumps `state_version`. Can only
+    /// be called by the contract itself.
+    #[init]
+    pub fn migrate() -> Self {
+        assert_self();
+        let mut res: Self = env::state_read().expect("EthClient is not initialized");
+        res.state_version += 1;
+        res
+    }
+
     #[result_serializer(borsh)]
     pub fn initialized() -> bool {
         env::state_read::<EthClient>().is_some()
     }
 
+    /// Returns the methods of the contract as `name: purpose` strings, for generic tooling.
     #[result_serializer(borsh)]
-    pub fn dag_merkle_root(&self, #[serializer(borsh)] epoch: u64) -> H128 {
-        self.dags_merkle_roots[(&epoch - self.dags_start_epoch) as usize]
+    pub fn interface(&self) -> Vec<String> {
+        INTERFACE
+            .iter()
+            .map(|(name, purpose)| format!("{}: {}", name, purpose))
+            .collect()
     }
 
+    /// Returns the keccak256 of the data computed by the same host function the client uses for
+    /// the header hashes.
     #[result_serializer(borsh)]
-    pub fn last_block_number(&self) -> u64 {
-        self.infos
-            .get(&self.best_header_hash)
-            .unwrap_or_default()
-            .number
+    pub fn keccak256(&self, #[serializer(borsh)] data: Vec<u8>) -> H256 {
+        near_keccak256(&data).into()
     }
 
-    /// Returns the block hash from the canonical chain.
+    /// Returns the prefix of the storage keys of all collections. The key prefix of each collection
+    /// is this prefix followed by the single byte identifying the collection.
     #[result_serializer(borsh)]
-    pub fn block_hash(&self, #[serializer(borsh)] index: u64) -> Option<H256> {
-        self.canonical_header_hashes.get(&index)
+    pub fn storage_prefix(&self) -> Vec<u8> {
+        self.prefix.clone()
     }
 
-    /// Returns all hashes known for that height.
+    /// Returns the hash of the header the client was initialized with. Every other header is only
+    /// accepted as its descendant, so this is the single anchor the client trusts.
     #[result_serializer(borsh)]
-    pub fn known_hashes(&self, #[serializer(borsh)] index: u64) -> Vec<H256> {
-        self.all_header_hashes.get(&index).unwrap_or_default()
+    pub fn trust_root(&self) -> H256 {
+        self.trust_root
     }
 
-    /// Returns block hash and the number of confirmations.
+    /// Returns the version of the state layout.
     #[result_serializer(borsh)]
-    pub fn block_hash_safe(&self, #[serializer(borsh)] index: u64) -> Option<H256> {
-        let header_hash = self.block_hash(index)?;
-        let last_block_number = self.last_block_number();
-        if index + self.num_confirmations > last_block_number {
-            None
-        } else {
-            Some(header_hash)
-        }
+    pub fn state_version(&self) -> u32 {
+        self.state_version
     }
 
-    /// Add the block header to the client.
-    /// `block_header` -- RLP-encoded Ethereum header;
-    /// `dag_nodes` -- dag nodes with their merkle proofs.
     #[result_serializer(borsh)]
-    pub fn add_block_header(
-        &mut self,
-        #[serializer(borsh)] block_header: Vec<u8>,
-        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
-    ) {
-        let header: BlockHeader = rlp::decode(block_header.as_slice()).unwrap();
-
-        if let Some(trusted_signer) = &self.trusted_signer {
-            assert!(
-                &env::signer_account_id() == trusted_signer,
-                "Eth-client is deployed as trust mode, only trusted_signer can add a new header"
-            );
-        } else {
-            let prev = self
-                .headers
-                .get(&header.parent_hash)
-                .expect("Parent header should be present to add a new header");
-            assert!(
-                self.verify_header(&header, &prev, &dag_nodes),
-                "The new header {} should be valid",
-                header.number
-            );
+    pub fn dag_merkle_root(&self, #[serializer(borsh)] epoch: u64) -> H128 {
+        match self.find_dag_merkle_root(epoch) {
+            Ok(root) => root,
+            Err(message) => panic!("{}", message),
         }
+    }
 
-        self.record_header(header);
+    /// Returns the DAG merkle roots for up to `count` epochs starting from `start_epoch`.
+    /// At most `MAX_QUERY_LENGTH` roots are returned.
+    #[result_serializer(borsh)]
+    pub fn dag_merkle_roots(
+        &self,
+        #[serializer(borsh)] start_epoch: u64,
+        #[serializer(borsh)] count: u64,
+    ) -> Vec<H128> {
+        let offset = match start_epoch.checked_sub(self.dags_start_epoch) {
+            Some(offset) => offset as usize,
+            None => return vec![],
+        };
+        self.dags_merkle_roots
+            .iter()
+            .skip(offset)
+            .take(std::cmp::min(count, MAX_QUERY_LENGTH) as usize)
+            .cloned()
+            .collect()
     }
-}
 
-impl EthClient {
-    /// Record the header. If needed update the canonical chain and perform the GC.
-    fn record_header(&mut self, header: BlockHeader) {
-        let best_info = self.infos.get(&self.best_header_hash).unwrap();
-        let header_hash = header.hash.unwrap();
-        let header_number = header.number;
-        if header_number + self.finalized_gc_threshold < best_info.number {
-            panic!("Header is too old to have a chance to appear on the canonical chain.");
-        }
+    /// Returns the commitment to the current DAG merkle roots.
+    #[result_serializer(borsh)]
+    pub fn dag_roots_commitment(&self) -> H256 {
+        self.dag_roots_hash
+    }
 
-        let parent_info = self
-            .infos
-            .get(&header.parent_hash)
-            .expect("Header has unknown parent. Parent should be submitted first.");
+    /// Returns how many `DoubleNodeWithMerkleProof` have to be submitted with an ethash header.
+    #[result_serializer(borsh)]
+    pub fn required_dag_nodes(&self) -> u64 {
+        ETHASH_ACCESSES
+    }
 
-        // Record this header in `all_hashes`.
-        let mut all_hashes = self
-            .all_header_hashes
-            .get(&header_number)
-            .unwrap_or_default();
-        assert!(
-            all_hashes.iter().find(|x| **x == header_hash).is_none(),
-            "Header is already known. Number: {}",
-            header_number
-        );
-        all_hashes.push(header_hash);
-        self.all_header_hashes.insert(&header_number, &all_hashes);
+    /// Returns whether adding new headers is suspended.
+    #[result_serializer(borsh)]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
 
-        // Record full information about this header.
-        self.headers.insert(&header_hash, &header);
-        let info = HeaderInfo {
-            total_difficulty: parent_info.total_difficulty + header.difficulty,
-            parent_hash: header.parent_hash.clone(),
-            number: header_number,
-        };
-        self.infos.insert(&header_hash, &info);
+    /// Returns the epoch of the first DAG merkle root and the epoch length in blocks.
+    #[result_serializer(borsh)]
+    pub fn dag_config(&self) -> (u64, u64) {
+        (self.dags_start_epoch, EPOCH_LENGTH)
+    }
 
-        // Check if canonical chain needs to be updated.
-        if info.total_difficulty > best_info.total_difficulty
-            || (info.total_difficulty == best_info.total_difficulty
-                && header.difficulty % 2 == U256::default())
-        {
-            // If the new header has a lower number than the previous header, we need to clean it
-            // going forward.
-            if best_info.number > info.number {
-                for number in info.number + 1..=best_info.number {
-                    self.canonical_header_hashes.remove(&number);
-                }
-            }
-            // Replacing the global best header hash.
-            self.best_header_hash = header_hash;
-            self.canonical_header_hashes
-                .insert(&header_number, &header_hash);
+    /// Returns the number of epochs after the epoch of the best header that are still covered by
+    /// the DAG merkle roots. New roots should be submitted before it drops to zero.
+    #[result_serializer(borsh)]
+    pub fn epochs_remaining(&self) -> u64 {
+        let epoch = self.last_block_number() / EPOCH_LENGTH;
+        (self.dags_start_epoch + self.dags_merkle_roots.len() as u64).saturating_sub(epoch + 1)
+    }
 
-            // Replacing past hashes until we converge into the same parent.
-            // Starting from the parent hash.
-            let mut number = header.number - 1;
-            let mut current_hash = info.parent_hash;
-            loop {
-                let prev_value = self.canonical_header_hashes.insert(&number, &current_hash);
-                // If the current block hash is 0 (unlikely), or the previous hash matches the
-                // current hash, then the chains converged and we can stop now.
-                if number == 0 || prev_value == Some(current_hash) {
-                    break;
-                }
-                // Check if there is an info to get the parent hash
-                if let Some(info) = self.infos.get(&current_hash) {
-                    current_hash = info.parent_hash;
-                } else {
-                    break;
-                }
-                number -= 1;
-            }
-            if header_number >= self.hashes_gc_threshold {
-                self.gc_canonical_chain(header_number - self.hashes_gc_threshold);
-            }
-            if header_number >= self.finalized_gc_threshold {
-                self.gc_headers(header_number - self.finalized_gc_threshold);
-            }
-        }
+    /// Returns whether there is no DAG merkle root for the epoch of the block, so that the block
+    /// can't be verified.
+    #[result_serializer(borsh)]
+    pub fn needs_dag_root_for_block(&self, #[serializer(borsh)] block_number: u64) -> bool {
+        (block_number / EPOCH_LENGTH)
+            .checked_sub(self.dags_start_epoch)
+            .map_or(true, |offset| offset >= self.dags_merkle_roots.len() as u64)
     }
 
-    /// Remove hashes from the canonical chain that are at least as old as the given header number.
-    fn gc_canonical_chain(&mut self, mut header_number: u64) {
-        loop {
-            if self.canonical_header_hashes.get(&header_number).is_some() {
-                self.canonical_header_hashes.remove(&header_number);
-                if header_number == 0 {
-                    break;
-                } else {
-                    header_number -= 1;
-                }
-            } else {
-                break;
-            }
-        }
+    /// Returns whether the DAG merkle root of the epoch following the one of the best header is
+    /// configured, so that the headers of that epoch can be verified once the chain reaches it.
+    #[result_serializer(borsh)]
+    pub fn ready_for_next_epoch(&self) -> bool {
+        let next_epoch = self.last_block_number() / EPOCH_LENGTH + 1;
+        !self.needs_dag_root_for_block(next_epoch.saturating_mul(EPOCH_LENGTH))
     }
 
-    /// Remove information about the headers that are at least as old as the given header number.
-    fn gc_headers(&mut self, mut header_number: u64) {
-        loop {
-            if let Some(all_headers) = self.all_header_hashes.get(&header_number) {
-                for hash in all_headers {
-                    self.headers.remove(&hash);
-                    self.infos.remove(&hash);
-                }
-                self.all_header_hashes.remove(&header_number);
-                if header_number == 0 {
-                    break;
-                } else {
-                    header_number -= 1;
-                }
-            } else {
-                break;
-            }
-        }
+    /// Returns the epochs of the blocks on the canonical chain in the increasing order. The
+    /// canonical chain is contiguous, so these are the epochs from the one of
+    /// `earliest_available_block` to the one of the best header. At most `MAX_QUERY_LENGTH` epochs
+    /// are returned.
+    #[result_serializer(borsh)]
+    pub fn epochs_in_use(&self) -> Vec<u64> {
+        let first = self.earliest_available_block() / EPOCH_LENGTH;
+        let last = self.last_block_number() / EPOCH_LENGTH;
+        (first..=last).take(MAX_QUERY_LENGTH as usize).collect()
     }
 
-    /// Verify PoW of the header.
-    fn verify_header(
-        &self,
-        header: &BlockHeader,
-        prev: &BlockHeader,
-        dag_nodes: &[DoubleNodeWithMerkleProof],
-    ) -> bool {
-        let (_mix_hash, result) = self.hashimoto_merkle(
-            &header.partial_hash.unwrap(),
-            &header.nonce,
-            header.number,
-            dag_nodes,
-        );
+    /// Returns whether the canonical chain has a header for every block of the epoch that falls
+    /// within the retained window, i.e. between `earliest_available_block` and the best header.
+    /// An epoch without retained blocks is not complete. At most `MAX_QUERY_LENGTH` blocks are
+    /// checked, starting from the lowest retained one.
+    #[result_serializer(borsh)]
+    pub fn epoch_complete(&self, #[serializer(borsh)] epoch: u64) -> bool {
+        let numbers = self.retained_numbers_in_epoch(epoch);
+        numbers.start() <= numbers.end()
+            && numbers
+                .take(MAX_QUERY_LENGTH as usize)
+                .all(|number| self.canonical_header_hashes.get(&number).is_some())
+    }
 
-        //
-        // See YellowPaper formula (50) in section 4.3.4
-        // 1. Simplified difficulty check to conform adjusting difficulty bomb
-        // 2. Added condition: header.parent_hash() == prev.hash()
-        //
-        U256((result.0).0.into()) < U256(ethash::cross_boundary(header.difficulty.0))
-            && (!self.validate_ethash
-                || (header.difficulty < header.difficulty * 101 / 100
-                    && header.difficulty > header.difficulty * 99 / 100))
-            && header.gas_used <= header.gas_limit
-            && header.gas_limit < prev.gas_limit * 1025 / 1024
-            && header.gas_limit > prev.gas_limit * 1023 / 1024
-            && header.gas_limit >= U256(5000.into())
-            && header.timestamp > prev.timestamp
-            && header.number == prev.number + 1
-            && header.parent_hash == prev.hash.unwrap()
-            && header.extra_data.len() <= 32
+    /// Returns how many blocks of the epoch that fall within the retained window are on the
+    /// canonical chain. Only the numbers of the retained window are scanned.
+    #[result_serializer(borsh)]
+    pub fn canonical_count_in_epoch(&self, #[serializer(borsh)] epoch: u64) -> u64 {
+        self.retained_numbers_in_epoch(epoch)
+            .filter(|number| self.canonical_header_hashes.get(number).is_some())
+            .count() as u64
     }
 
-    /// Verify merkle paths to the DAG nodes.
+    /// Returns the advisory amount of gas to attach to `add_block_header` for the next header.
+    /// The DAG grows with every epoch, and so does the depth of the merkle proofs that have to be
+    /// verified for each DAG node.
+    #[result_serializer(borsh)]
+    pub fn recommended_gas(&self) -> u64 {
+        let epoch = (self.last_block_number() + 1) / EPOCH_LENGTH;
+        // The DAG is accessed in 128-byte items.
+        let items = (ethash::get_full_size(epoch as usize) / 128) as u64;
+        let proof_depth = 64 - u64::from((items - 1).leading_zeros());
+        BASE_ADD_HEADER_GAS + proof_depth * ADD_HEADER_GAS_PER_PROOF_LEVEL
+    }
+
+    #[result_serializer(borsh)]
+    pub fn last_block_number(&self) -> u64 {
+        self.infos
+            .get(&self.best_header_hash)
+            .unwrap_or_default()
+            .number
+    }
+
+    /// Returns the number of the best header minus `finalized_gc_threshold`: the headers at and
+    /// below it are eligible for the GC of the headers.
+    #[result_serializer(borsh)]
+    pub fn finality_horizon(&self) -> u64 {
+        self.last_block_number()
+            .saturating_sub(self.finalized_gc_threshold)
+    }
+
+    /// Returns by how many blocks the best header is behind `remote_tip`, or 0 if it is not.
+    #[result_serializer(borsh)]
+    pub fn blocks_behind(&self, #[serializer(borsh)] remote_tip: u64) -> u64 {
+        remote_tip.saturating_sub(self.last_block_number())
+    }
+
+    /// Returns how many headers of the given epoch were recorded.
+    #[result_serializer(borsh)]
+    pub fn epoch_submission_count(&self, #[serializer(borsh)] epoch: u64) -> u64 {
+        self.epoch_submissions.get(&epoch).unwrap_or_default()
+    }
+
+    /// Returns whether the last submitted header, or the buffered headers it connected, caused a
+    /// reorg of the canonical chain.
+    #[result_serializer(borsh)]
+    pub fn last_was_reorg(&self) -> bool {
+        self.last_submit_was_reorg
+    }
+
+    /// Returns up to `k` of the most recent reorgs, the newest first. At most `MAX_REORG_EVENTS`
+    /// reorgs are kept.
+    #[result_serializer(borsh)]
+    pub fn recent_reorgs(&self, #[serializer(borsh)] k: u64) -> Vec<ReorgEvent> {
+        self.reorg_events
+            .iter()
+            .rev()
+            .take(k as usize)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns how many submitted headers had their ethash PoW verified.
+    #[result_serializer(borsh)]
+    pub fn pow_verifications(&self) -> u64 {
+        self.pow_verifications
+    }
+
+    /// Returns the lowest block number that is still present on the canonical chain.
+    #[result_serializer(borsh)]
+    pub fn earliest_available_block(&self) -> u64 {
+        std::cmp::min(self.lowest_canonical_number, self.last_block_number())
+    }
+
+    /// Returns whether the canonical block with the given number can still be fetched, i.e. it is
+    /// between `earliest_available_block` and the best header.
+    #[result_serializer(borsh)]
+    pub fn is_retained(&self, #[serializer(borsh)] number: u64) -> bool {
+        number >= self.earliest_available_block() && number <= self.last_block_number()
+    }
+
+    /// Returns the borsh-serialized `Checkpoint` of the best header, which can be used to bootstrap
+    /// a new client with `init_with_checkpoint`.
+    #[result_serializer(borsh)]
+    pub fn export_checkpoint(&self) -> Vec<u8> {
+        let best_header = self
+            .headers
+            .get(&self.best_header_hash)
+            .expect("The best header is stored compactly");
+        Checkpoint {
+            best_header_hash: self.best_header_hash,
+            best_header_info: self.infos.get(&self.best_header_hash).unwrap(),
+            best_header: rlp::encode(&best_header),
+            validate_ethash: self.validate_ethash,
+            dags_start_epoch: self.dags_start_epoch,
+            dags_merkle_roots: self.dags_merkle_roots.clone(),
+            hashes_gc_threshold: self.hashes_gc_threshold,
+            finalized_gc_threshold: self.finalized_gc_threshold,
+            finality_mode: self.finality_mode.clone(),
+            trusted_signer: self.trusted_signer.clone(),
+            max_gc_per_call: self.max_gc_per_call,
+            tie_break: self.tie_break,
+            max_extra_data_bytes: self.max_extra_data_bytes,
+            max_header_gap: self.max_header_gap,
+            consensus: self.consensus.clone(),
+            on_missing_parent: self.on_missing_parent,
+            difficulty_tolerance_permille: self.difficulty_tolerance_permille,
+            require_empty_uncles: self.require_empty_uncles,
+            max_orphan_reprocess_per_call: self.max_orphan_reprocess_per_call,
+            min_submission_deposit: self.min_submission_deposit,
+            store_full_headers: self.store_full_headers,
+            min_header_timestamp: self.min_header_timestamp,
+        }
+        .try_to_vec()
+        .unwrap()
+    }
+
+    /// Returns storage counters of the client in a single call.
+    #[result_serializer(borsh)]
+    pub fn stats(&self) -> BridgeStats {
+        BridgeStats {
+            headers_count: self.headers.len() + self.compact_headers.len(),
+            canonical_count: self.canonical_header_hashes.len(),
+            reorg_count: self.reorg_count,
+            earliest_available_block: self.earliest_available_block(),
+        }
+    }
+
+    /// Returns the block hash from the canonical chain.
+    #[result_serializer(borsh)]
+    pub fn block_hash(&self, #[serializer(borsh)] index: u64) -> Option<H256> {
+        self.canonical_header_hashes.get(&index)
+    }
+
+    /// Returns the RLP-encoded header for each of the given hashes, or `None` for the unknown,
+    /// garbage collected and compactly stored headers. At most `MAX_QUERY_LENGTH` hashes are
+    /// looked up.
+    #[result_serializer(borsh)]
+    pub fn get_headers(&self, #[serializer(borsh)] hashes: Vec<H256>) -> Vec<Option<Vec<u8>>> {
+        hashes
+            .iter()
+            .take(MAX_QUERY_LENGTH as usize)
+            .map(|hash| self.headers.get(hash).map(|header| rlp::encode(&header)))
+            .collect()
+    }
+
+    /// Returns the RLP-encoded best header, or `None` if it is not stored or stored compactly.
+    #[result_serializer(borsh)]
+    pub fn tip_header(&self) -> Option<Vec<u8>> {
+        self.headers
+            .get(&self.best_header_hash)
+            .map(|header| rlp::encode(&header))
+    }
+
+    /// Returns the RLP-encoded canonical header with the given number. Returns `None` if there is
+    /// no such header, it was already garbage collected or it is stored compactly.
+    #[result_serializer(borsh)]
+    pub fn canonical_header(&self, #[serializer(borsh)] number: u64) -> Option<Vec<u8>> {
+        let hash = self.canonical_header_hashes.get(&number)?;
+        self.headers.get(&hash).map(|header| rlp::encode(&header))
+    }
+
+    /// Returns whether the given hash is the canonical block at the given number.
+    #[result_serializer(borsh)]
+    pub fn verify_block(
+        &self,
+        #[serializer(borsh)] number: u64,
+        #[serializer(borsh)] hash: H256,
+    ) -> bool {
+        self.canonical_header_hashes.get(&number) == Some(hash)
+    }
+
+    /// Returns whether the RLP-encoded headers form a chain that extends the best header. Only the
+    /// parent hashes are checked. An empty batch or a batch with a malformed header does not
+    /// extend the best header.
+    #[result_serializer(borsh)]
+    pub fn extends_tip(&self, #[serializer(borsh)] headers: Vec<Vec<u8>>) -> bool {
+        if headers.is_empty() {
+            return false;
+        }
+        let mut parent_hash = self.best_header_hash;
+        for header_rlp in headers {
+            let header: BlockHeader = match rlp::decode(header_rlp.as_slice()) {
+                Ok(header) => header,
+                Err(_) => return false,
+            };
+            if header.parent_hash != parent_hash {
+                return false;
+            }
+            parent_hash = header.hash.unwrap();
+        }
+        true
+    }
+
+    /// Returns whether the RLP-encoded header is the canonical block at its number. A malformed
+    /// header is not canonical.
+    #[result_serializer(borsh)]
+    pub fn matches_canonical(&self, #[serializer(borsh)] header_rlp: Vec<u8>) -> bool {
+        match rlp::decode::<BlockHeader>(header_rlp.as_slice()) {
+            Ok(header) => self.verify_block(header.number, header.hash.unwrap()),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the hash of the highest final canonical block whose timestamp is not later than
+    /// `timestamp`. Scans back at most `MAX_QUERY_LENGTH` blocks and only over the headers that
+    /// were not garbage collected yet.
+    #[result_serializer(borsh)]
+    pub fn finalized_at_timestamp(&self, #[serializer(borsh)] timestamp: u64) -> Option<H256> {
+        let mut number = self.safe_block_number()?;
+        for _ in 0..MAX_QUERY_LENGTH {
+            let hash = self.canonical_header_hashes.get(&number)?;
+            if self.infos.get(&hash)?.timestamp <= timestamp {
+                return Some(hash);
+            }
+            number = number.checked_sub(1)?;
+        }
+        None
+    }
+
+    /// Returns the highest canonical block number not above `number` together with its hash, so a
+    /// number beyond the best header resolves to the best header. Scans back at most
+    /// `MAX_QUERY_LENGTH` blocks.
+    #[result_serializer(borsh)]
+    pub fn canonical_at_or_below(&self, #[serializer(borsh)] number: u64) -> Option<(u64, H256)> {
+        let mut number = std::cmp::min(number, self.last_block_number());
+        for _ in 0..MAX_QUERY_LENGTH {
+            if let Some(hash) = self.canonical_header_hashes.get(&number) {
+                return Some((number, hash));
+            }
+            number = number.checked_sub(1)?;
+        }
+        None
+    }
+
+    /// Returns the number of the header with the given hash. Returns `None` for unknown headers
+    /// and for headers older than `finalized_gc_threshold` that were already garbage collected.
+    #[result_serializer(borsh)]
+    pub fn number_of(&self, #[serializer(borsh)] hash: H256) -> Option<u64> {
+        self.infos.get(&hash).map(|info| info.number)
+    }
+
+    /// Returns 0 if the header is unknown or already garbage collected, 1 if it is known but not on
+    /// the canonical chain, and 2 if it is on the canonical chain.
+    #[result_serializer(borsh)]
+    pub fn header_status(&self, #[serializer(borsh)] hash: H256) -> u8 {
+        match self.infos.get(&hash) {
+            Some(info) if self.verify_block(info.number, hash) => 2,
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+
+    /// Returns whether the PoW of the header was verified, as opposed to the header being trusted
+    /// because it is the first header or was added by the trusted signer.
+    #[result_serializer(borsh)]
+    pub fn is_pow_verified(&self, #[serializer(borsh)] hash: H256) -> Option<bool> {
+        self.infos.get(&hash).map(|info| info.pow_verified)
+    }
+
+    /// Returns the number and the difficulty of the header.
+    #[result_serializer(borsh)]
+    pub fn header_number_difficulty(&self, #[serializer(borsh)] hash: H256) -> Option<(u64, U256)> {
+        self.infos
+            .get(&hash)
+            .map(|info| (info.number, info.difficulty))
+    }
+
+    /// Returns the state, transactions and receipts roots of the header, which are needed to
+    /// verify merkle patricia proofs against it.
+    #[result_serializer(borsh)]
+    pub fn header_roots(&self, #[serializer(borsh)] hash: H256) -> Option<(H256, H256, H256)> {
+        self.stored_header(&hash).map(|header| {
+            (
+                header.state_root,
+                header.transactions_root,
+                header.receipts_root,
+            )
+        })
+    }
+
+    /// Returns the nonce and the mix hash of the header, which are needed to verify its PoW.
+    #[result_serializer(borsh)]
+    pub fn header_seal(&self, #[serializer(borsh)] hash: H256) -> Option<(H64, H256)> {
+        self.stored_header(&hash)
+            .map(|header| (header.nonce, header.mix_hash))
+    }
+
+    /// Returns the 256-byte logs bloom of the header, or `None` if it is unknown or stored
+    /// compactly.
+    #[result_serializer(borsh)]
+    pub fn header_logs_bloom(&self, #[serializer(borsh)] hash: H256) -> Option<Vec<u8>> {
+        self.headers
+            .get(&hash)
+            .map(|header| (header.log_bloom.0).0.to_vec())
+    }
+
+    /// Verifies the RLP-encoded header against its stored parent without recording it. Returns
+    /// the number, the hash, the state root and the receipts root of the header if it is valid,
+    /// or `None` if it is malformed, invalid or its parent is unknown.
+    #[result_serializer(borsh)]
+    pub fn verify_and_extract(
+        &self,
+        #[serializer(borsh)] header_rlp: Vec<u8>,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) -> Option<(u64, H256, H256, H256)> {
+        let header: BlockHeader = rlp::decode(header_rlp.as_slice()).ok()?;
+        let prev = self.stored_header(&header.parent_hash)?;
+        self.verify_header(&header, &prev, &dag_nodes).ok()?;
+        Some((
+            header.number,
+            header.hash.unwrap(),
+            header.state_root,
+            header.receipts_root,
+        ))
+    }
+
+    /// Verifies that `headers` is a valid chain from a stored checkpoint header to the best header.
+    /// `dag_nodes[i]` are the DAG nodes of `headers[i + 1]`. Every header after the first one is
+    /// verified against its predecessor, the last one has to be the best header and the
+    /// difficulties of the headers after the first one have to add up to the difference of the
+    /// stored total difficulties of the best header and the checkpoint header. A chain with a
+    /// malformed header is not valid.
+    #[result_serializer(borsh)]
+    pub fn verify_checkpoint_link(
+        &self,
+        #[serializer(borsh)] headers: Vec<Vec<u8>>,
+        #[serializer(borsh)] dag_nodes: Vec<Vec<DoubleNodeWithMerkleProof>>,
+    ) -> bool {
+        if headers.is_empty() || dag_nodes.len() + 1 != headers.len() {
+            return false;
+        }
+        let headers: Vec<BlockHeader> = match headers
+            .iter()
+            .map(|header| rlp::decode(header.as_slice()))
+            .collect()
+        {
+            Ok(headers) => headers,
+            Err(_) => return false,
+        };
+        let checkpoint_info = match self.infos.get(&headers[0].hash.unwrap()) {
+            Some(info) => info,
+            None => return false,
+        };
+        let mut total_difficulty = checkpoint_info.total_difficulty.0;
+        for (pair, nodes) in headers.windows(2).zip(dag_nodes.iter()) {
+            if self.verify_header(&pair[1], &pair[0], nodes).is_err() {
+                return false;
+            }
+            total_difficulty = match total_difficulty.checked_add(pair[1].difficulty.0) {
+                Some(total_difficulty) => total_difficulty,
+                None => return false,
+            };
+        }
+        let best_info = self.infos.get(&self.best_header_hash).unwrap();
+        headers.last().unwrap().hash == Some(self.best_header_hash)
+            && U256(total_difficulty) == best_info.total_difficulty
+    }
+
+    /// Re-runs the verification of the stored header against its stored parent. Returns false if
+    /// either of them is unknown or already garbage collected, or if the header is stored
+    /// compactly, since the clique seal is a part of the dropped extra data.
+    #[result_serializer(borsh)]
+    pub fn reverify(
+        &self,
+        #[serializer(borsh)] hash: H256,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) -> bool {
+        match self.headers.get(&hash) {
+            Some(header) => match self.stored_header(&header.parent_hash) {
+                Some(prev) => self.verify_header(&header, &prev, &dag_nodes).is_ok(),
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Returns whether the canonical header with the given number is a child of the canonical
+    /// header with the previous number. Returns false if either of them is missing.
+    #[result_serializer(borsh)]
+    pub fn canonical_parent_ok(&self, #[serializer(borsh)] number: u64) -> bool {
+        let parent_number = match number.checked_sub(1) {
+            Some(parent_number) => parent_number,
+            None => return false,
+        };
+        match (
+            self.canonical_header_hashes
+                .get(&number)
+                .and_then(|hash| self.stored_header(&hash)),
+            self.canonical_header_hashes.get(&parent_number),
+        ) {
+            (Some(header), Some(parent_hash)) => header.parent_hash == parent_hash,
+            _ => false,
+        }
+    }
+
+    /// Returns all hashes known for that height.
+    #[result_serializer(borsh)]
+    pub fn known_hashes(&self, #[serializer(borsh)] index: u64) -> Vec<H256> {
+        self.all_header_hashes.get(&index).unwrap_or_default()
+    }
+
+    /// Returns the hashes of up to `n` canonical blocks from the best header downward, stopping at
+    /// the garbage collected part of the canonical chain. At most `MAX_QUERY_LENGTH` hashes are
+    /// returned.
+    #[result_serializer(borsh)]
+    pub fn recent_canonical(&self, #[serializer(borsh)] n: u64) -> Vec<H256> {
+        let last_block_number = self.last_block_number();
+        let mut hashes = vec![];
+        for offset in 0..std::cmp::min(n, MAX_QUERY_LENGTH) {
+            match last_block_number
+                .checked_sub(offset)
+                .and_then(|number| self.canonical_header_hashes.get(&number))
+            {
+                Some(hash) => hashes.push(hash),
+                None => break,
+            }
+        }
+        hashes
+    }
+
+    /// Returns the number of known headers for each of up to `count` heights starting from
+    /// `start`. At most `MAX_QUERY_LENGTH` counts are returned.
+    #[result_serializer(borsh)]
+    pub fn fork_counts(
+        &self,
+        #[serializer(borsh)] start: u64,
+        #[serializer(borsh)] count: u64,
+    ) -> Vec<u64> {
+        let end = start.saturating_add(std::cmp::min(count, MAX_QUERY_LENGTH));
+        (start..end)
+            .map(|number| {
+                self.all_header_hashes
+                    .get(&number)
+                    .map_or(0, |hashes| hashes.len() as u64)
+            })
+            .collect()
+    }
+
+    /// Returns the block hash from the canonical chain if the block is final.
+    #[result_serializer(borsh)]
+    pub fn block_hash_safe(&self, #[serializer(borsh)] index: u64) -> Option<H256> {
+        let header_hash = self.block_hash(index)?;
+        if index <= self.safe_block_number()? {
+            Some(header_hash)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the highest number of a final canonical block, or `None` if there is no such block
+    /// yet. In the difficulty mode it is the block found when the best header last changed, which
+    /// advances by at most `MAX_QUERY_LENGTH` blocks per change.
+    #[result_serializer(borsh)]
+    pub fn safe_block_number(&self) -> Option<u64> {
+        match &self.finality_mode {
+            FinalityMode::FixedDepth(confirmations) => {
+                self.last_block_number().checked_sub(*confirmations)
+            }
+            FinalityMode::Difficulty(_) => self.difficulty_safe_number,
+        }
+    }
+
+    /// Returns whether the given hash is the canonical block at the given number and the block is
+    /// final.
+    #[result_serializer(borsh)]
+    pub fn is_finalized_block(
+        &self,
+        #[serializer(borsh)] number: u64,
+        #[serializer(borsh)] hash: H256,
+    ) -> bool {
+        self.verify_block(number, hash)
+            && self
+                .safe_block_number()
+                .map_or(false, |safe_block_number| number <= safe_block_number)
+    }
+
+    /// Returns whether the client fell behind: either the best header is older than
+    /// `max_age_seconds`, or no header was recorded for `max_age_seconds`.
+    #[result_serializer(borsh)]
+    pub fn is_stalled(&self, #[serializer(borsh)] max_age_seconds: u64) -> bool {
+        let now = env::block_timestamp();
+        let tip_timestamp = self.infos.get(&self.best_header_hash).unwrap().timestamp;
+        tip_timestamp.saturating_add(max_age_seconds) < now / 1_000_000_000
+            || self
+                .last_update_block_timestamp
+                .saturating_add(max_age_seconds.saturating_mul(1_000_000_000))
+                < now
+    }
+
+    /// Returns the total difficulty a child of the given parent with the given difficulty would
+    /// have. Returns `None` if the parent is unknown or the total difficulty overflows.
+    #[result_serializer(borsh)]
+    pub fn prospective_total_difficulty(
+        &self,
+        #[serializer(borsh)] parent_hash: H256,
+        #[serializer(borsh)] header_difficulty: U256,
+    ) -> Option<U256> {
+        self.infos
+            .get(&parent_hash)?
+            .total_difficulty
+            .0
+            .checked_add(header_difficulty.0)
+            .map(U256)
+    }
+
+    /// Returns whether the stored total difficulty of the header equals `claimed`. Unknown or
+    /// already garbage collected headers never match.
+    #[result_serializer(borsh)]
+    pub fn confirm_total_difficulty(
+        &self,
+        #[serializer(borsh)] hash: H256,
+        #[serializer(borsh)] claimed: U256,
+    ) -> bool {
+        self.infos
+            .get(&hash)
+            .map_or(false, |info| info.total_difficulty == claimed)
+    }
+
+    /// Recomputes the total difficulty of the canonical header `start + count - 1` from the stored
+    /// total difficulty of the canonical header `start - 1` and the difficulties of the canonical
+    /// headers in between. Comparing it with the stored value detects corrupted infos.
+    /// Returns `None` if any of these headers was already garbage collected or the total
+    /// difficulty overflows. At most `MAX_QUERY_LENGTH` headers are summed.
+    #[result_serializer(borsh)]
+    pub fn recompute_total_difficulty(
+        &self,
+        #[serializer(borsh)] start: u64,
+        #[serializer(borsh)] count: u64,
+    ) -> Option<U256> {
+        let parent_hash = self.canonical_header_hashes.get(&start.checked_sub(1)?)?;
+        let mut total_difficulty = self.infos.get(&parent_hash)?.total_difficulty.0;
+        let stop = start.saturating_add(std::cmp::min(count, MAX_QUERY_LENGTH));
+        for number in start..stop {
+            let header_hash = self.canonical_header_hashes.get(&number)?;
+            total_difficulty =
+                total_difficulty.checked_add(self.infos.get(&header_hash)?.difficulty.0)?;
+        }
+        Some(U256(total_difficulty))
+    }
+
+    /// Returns whether `ancestor` is reached by following the parents of `descendant` for at most
+    /// `max_depth` steps. Only headers within the `finalized_gc_threshold` window can be walked.
+    #[result_serializer(borsh)]
+    pub fn is_ancestor(
+        &self,
+        #[serializer(borsh)] ancestor: H256,
+        #[serializer(borsh)] descendant: H256,
+        #[serializer(borsh)] max_depth: u64,
+    ) -> bool {
+        let mut current_hash = descendant;
+        for _ in 0..max_depth {
+            match self.infos.get(&current_hash) {
+                Some(info) if info.parent_hash == ancestor => return true,
+                Some(info) => current_hash = info.parent_hash,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Returns the DAG dataset indices ethash accesses while hashing the header, in access order.
+    /// Every access after the first one depends on the data fetched before it, so the DAG nodes
+    /// still have to be supplied. Their merkle proofs are not checked. A malformed header has no
+    /// accesses.
+    #[result_serializer(borsh)]
+    pub fn dag_access_indices(
+        &self,
+        #[serializer(borsh)] header_rlp: Vec<u8>,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) -> Vec<u64> {
+        let header: BlockHeader = match rlp::decode(header_rlp.as_slice()) {
+            Ok(header) => header,
+            Err(_) => return vec![],
+        };
+        let indices = std::cell::RefCell::new(vec![]);
+        ethash::hashimoto_with_hasher(
+            header.partial_hash.unwrap().0,
+            header.nonce.0,
+            ethash::get_full_size((header.number / EPOCH_LENGTH) as usize),
+            |offset| {
+                let idx = indices.borrow().len();
+                indices.borrow_mut().push(offset as u64);
+
+                let mut data = (dag_nodes[idx / 2].dag_nodes[idx % 2].0).0;
+                data[..32].reverse();
+                data[32..].reverse();
+                data.into()
+            },
+            near_keccak256,
+            near_keccak512,
+        );
+        indices.into_inner()
+    }
+
+    /// Returns whether every DAG node is proven against the merkle root of the epoch of the header
+    /// at the index ethash accesses it at, i.e. whether the nodes are supplied in the access order.
+    /// Returns false on the first misordered node and for a malformed header. The PoW itself is
+    /// not checked.
+    #[result_serializer(borsh)]
+    pub fn check_proof_ordering(
+        &self,
+        #[serializer(borsh)] header_rlp: Vec<u8>,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) -> bool {
+        if dag_nodes.len() as u64 != ETHASH_ACCESSES {
+            return false;
+        }
+        let header: BlockHeader = match rlp::decode(header_rlp.as_slice()) {
+            Ok(header) => header,
+            Err(_) => return false,
+        };
+        let merkle_root = self.dag_merkle_root(header.number / EPOCH_LENGTH);
+        let indices = self.dag_access_indices(header_rlp, dag_nodes.clone());
+        dag_nodes
+            .iter()
+            .enumerate()
+            .all(|(i, node)| node.apply_merkle_proof(indices[2 * i] / 2) == merkle_root)
+    }
+
+    /// Verifies the PoW of the canonical header with the given number and marks it as verified.
+    /// This upgrades headers that were trusted without verification, like the first header.
+    pub fn attach_pow_proof(
+        &mut self,
+        #[serializer(borsh)] number: u64,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) {
+        assert!(
+            self.validate_ethash,
+            "Eth-client should validate ethash to verify the PoW"
+        );
+        let header_hash = self
+            .canonical_header_hashes
+            .get(&number)
+            .expect("Header should be on the canonical chain");
+        let header = self
+            .stored_header(&header_hash)
+            .expect("Header should be present to verify its PoW");
+        assert!(
+            self.verify_pow(&header, &dag_nodes).is_ok(),
+            "The PoW of the header {} should be valid",
+            number
+        );
+        let mut info = self.infos.get(&header_hash).unwrap();
+        info.pow_verified = true;
+        self.infos.insert(&header_hash, &info);
+    }
+
+    /// Returns the lowest number for which header information is still stored, together with the
+    /// hashes of the headers with this number. These are the headers removed by the next GC step.
+    /// At most `MAX_QUERY_LENGTH` numbers are scanned.
+    #[result_serializer(borsh)]
+    pub fn next_gc_target(&self) -> Option<(u64, Vec<H256>)> {
+        (self.lowest_header_number..=self.last_block_number())
+            .take(MAX_QUERY_LENGTH as usize)
+            .find_map(|number| {
+                self.all_header_hashes
+                    .get(&number)
+                    .map(|hashes| (number, hashes))
+            })
+    }
+
+    /// Returns how many headers `prune_below` would remove for the given number, without
+    /// removing them. Like `prune_below`, it only counts the headers of the first
+    /// `max_gc_per_call` numbers.
+    #[result_serializer(borsh)]
+    pub fn gc_preview(&self, #[serializer(borsh)] up_to_number: u64) -> u64 {
+        let header_number = match up_to_number.checked_sub(1) {
+            Some(header_number) => header_number,
+            None => return 0,
+        };
+        self.headers_gc_range(header_number)
+            .filter_map(|number| self.all_header_hashes.get(&number))
+            .map(|all_headers| all_headers.len() as u64)
+            .sum()
+    }
+
+    /// Returns how many block numbers the GC would remove from the canonical chain and from the
+    /// header information if the given header became the best header, without removing them.
+    /// A malformed header can not become the best header, so it triggers no GC. In the difficulty
+    /// finality mode the blocks that the header would make final are not counted.
+    #[result_serializer(borsh)]
+    pub fn gc_impact_of(&self, #[serializer(borsh)] header_rlp: Vec<u8>) -> u64 {
+        let header: BlockHeader = match rlp::decode(header_rlp.as_slice()) {
+            Ok(header) => header,
+            Err(_) => return 0,
+        };
+        let safe_block_number = match &self.finality_mode {
+            FinalityMode::FixedDepth(confirmations) => header.number.checked_sub(*confirmations),
+            FinalityMode::Difficulty(_) => self.difficulty_safe_number,
+        };
+        let safe_block_number = match safe_block_number {
+            Some(safe_block_number) => safe_block_number,
+            None => return 0,
+        };
+        let canonical = header
+            .number
+            .checked_sub(self.hashes_gc_threshold)
+            .map_or(0, |number| {
+                self.canonical_gc_range(std::cmp::min(number, safe_block_number))
+                    .count()
+            });
+        let headers = header
+            .number
+            .checked_sub(self.finalized_gc_threshold)
+            .map_or(0, |number| {
+                self.headers_gc_range(std::cmp::min(number, safe_block_number))
+                    .count()
+            });
+        (canonical + headers) as u64
+    }
+
+    /// Removes the headers of an abandoned fork ahead of the regular GC, walking back from
+    /// `fork_tip` for at most `max_steps` headers. Stops at the first header that is canonical,
+    /// not final yet, already removed or still has a child, so no stored header loses its parent.
+    /// Can only be called by the contract itself.
+    pub fn prune_fork(
+        &mut self,
+        #[serializer(borsh)] fork_tip: H256,
+        #[serializer(borsh)] max_steps: u64,
+    ) {
+        assert_self();
+        let safe_block_number = match self.safe_block_number() {
+            Some(safe_block_number) => safe_block_number,
+            None => return,
+        };
+        let mut hash = fork_tip;
+        for _ in 0..max_steps {
+            let info = match self.infos.get(&hash) {
+                Some(info) => info,
+                None => break,
+            };
+            if info.number > safe_block_number
+                || self.verify_block(info.number, hash)
+                || self.has_child(&hash, info.number)
+            {
+                break;
+            }
+            self.remove_header(&hash);
+            if let Some(mut all_headers) = self.all_header_hashes.get(&info.number) {
+                all_headers.retain(|known| known != &hash);
+                if all_headers.is_empty() {
+                    self.all_header_hashes.remove(&info.number);
+                } else {
+                    self.all_header_hashes.insert(&info.number, &all_headers);
+                }
+            }
+            let epoch = info.number / EPOCH_LENGTH;
+            let submissions = self.epoch_submission_count(epoch);
+            self.epoch_submissions
+                .insert(&epoch, &submissions.saturating_sub(1));
+            hash = info.parent_hash;
+        }
+    }
+
+    /// Removes information about the headers with numbers below `up_to_number` ahead of the
+    /// regular GC. The best header is always kept. Like the regular GC, it removes at most
+    /// `max_gc_per_call` numbers. Can only be called by the contract itself.
+    pub fn prune_below(&mut self, #[serializer(borsh)] up_to_number: u64) {
+        assert_self();
+        assert!(
+            up_to_number <= self.last_block_number(),
+            "The best header can not be pruned"
+        );
+        if up_to_number > 0 {
+            self.gc_headers(up_to_number - 1);
+        }
+    }
+
+    /// Runs the GC for the current best header. The GC runs whenever the best header changes, so
+    /// this is only needed to finish passes cut short by `max_gc_per_call`. Calling it again
+    /// after the GC is complete has no effect.
+    pub fn run_gc(&mut self) {
+        self.maybe_gc(self.last_block_number());
+    }
+
+    /// Sets how many block numbers a GC pass can remove from each collection.
+    /// Can only be called by the contract itself.
+    pub fn set_max_gc_per_call(&mut self, #[serializer(borsh)] max_gc_per_call: u64) {
+        assert_self();
+        assert!(
+            max_gc_per_call > 0,
+            "GC should remove at least one number per call"
+        );
+        self.max_gc_per_call = max_gc_per_call;
+    }
+
+    /// Sets how the canonical chain is chosen between headers with equal total difficulty.
+    /// Can only be called by the contract itself.
+    pub fn set_tie_break(&mut self, #[serializer(borsh)] tie_break: TieBreak) {
+        assert_self();
+        self.tie_break = tie_break;
+    }
+
+    /// Sets the maximum length of the extra data of the accepted headers.
+    /// Can only be called by the contract itself.
+    pub fn set_max_extra_data_bytes(&mut self, #[serializer(borsh)] max_extra_data_bytes: u64) {
+        assert_self();
+        self.max_extra_data_bytes = max_extra_data_bytes;
+    }
+
+    /// Sets the maximum number of blocks by which a new header can be ahead of the best header.
+    /// Can only be called by the contract itself.
+    pub fn set_max_header_gap(&mut self, #[serializer(borsh)] max_header_gap: u64) {
+        assert_self();
+        self.max_header_gap = max_header_gap;
+    }
+
+    /// Sets how the seal of the new headers is verified. Can only be called by the contract
+    /// itself.
+    pub fn set_consensus(&mut self, #[serializer(borsh)] consensus: ConsensusMode) {
+        assert_self();
+        self.consensus = consensus;
+    }
+
+    /// Sets what happens to the new headers whose parent is unknown. Can only be called by the
+    /// contract itself.
+    pub fn set_on_missing_parent(
+        &mut self,
+        #[serializer(borsh)] on_missing_parent: OnMissingParent,
+    ) {
+        assert_self();
+        self.on_missing_parent = on_missing_parent;
+    }
+
+    /// Sets the allowed deviation of the ethash difficulty from the one expected from the parent,
+    /// in thousandths of the expected difficulty, at most 1000. Can only be called by the contract
+    /// itself.
+    pub fn set_difficulty_tolerance_permille(
+        &mut self,
+        #[serializer(borsh)] difficulty_tolerance_permille: u16,
+    ) {
+        assert_self();
+        assert!(
+            difficulty_tolerance_permille <= 1000,
+            "Difficulty tolerance can't exceed 1000 permille"
+        );
+        self.difficulty_tolerance_permille = difficulty_tolerance_permille;
+    }
+
+    /// Sets the maximum number of buffered headers recorded by a single call once their parent is
+    /// added. Can only be called by the contract itself.
+    pub fn set_max_orphan_reprocess_per_call(
+        &mut self,
+        #[serializer(borsh)] max_orphan_reprocess_per_call: u64,
+    ) {
+        assert_self();
+        assert!(
+            max_orphan_reprocess_per_call > 0,
+            "At least one buffered header should be recorded per call"
+        );
+        self.max_orphan_reprocess_per_call = max_orphan_reprocess_per_call;
+    }
+
+    /// Sets the deposit that has to be attached to every submission of a header. The deposit is
+    /// kept only for the headers that are added: the runtime returns it when the submission panics
+    /// and `add_block_header_result` returns it for the rejected headers. Can only be called by
+    /// the contract itself.
+    pub fn set_min_submission_deposit(
+        &mut self,
+        #[serializer(borsh)] min_submission_deposit: Balance,
+    ) {
+        assert_self();
+        self.min_submission_deposit = min_submission_deposit;
+    }
+
+    /// Sets the total difficulty of the first header, which `init` can't know and sets to zero,
+    /// so that the total difficulties of the descendants match the ones of the Ethereum chain.
+    /// Can only be called by the contract itself before any other header is added.
+    pub fn set_initial_total_difficulty(&mut self, #[serializer(borsh)] total_difficulty: U256) {
+        assert_self();
+        assert!(
+            self.best_header_hash == self.trust_root && self.infos.len() == 1,
+            "The initial total difficulty can only be set before other headers are added"
+        );
+        let mut info = self.infos.get(&self.trust_root).unwrap();
+        info.total_difficulty = total_difficulty;
+        self.infos.insert(&self.trust_root, &info);
+    }
+
+    /// Sets the minimum timestamp of new headers, in seconds. Can only be called by the contract
+    /// itself.
+    pub fn set_min_header_timestamp(&mut self, #[serializer(borsh)] min_header_timestamp: u64) {
+        assert_self();
+        self.min_header_timestamp = min_header_timestamp;
+    }
+
+    /// Sets whether new headers are stored in full or without their logs bloom and extra data.
+    /// Can only be called by the contract itself.
+    pub fn set_store_full_headers(&mut self, #[serializer(borsh)] store_full_headers: bool) {
+        assert_self();
+        self.store_full_headers = store_full_headers;
+    }
+
+    /// Sets whether only the headers without uncles are accepted. Can only be called by the
+    /// contract itself.
+    pub fn set_require_empty_uncles(&mut self, #[serializer(borsh)] require_empty_uncles: bool) {
+        assert_self();
+        self.require_empty_uncles = require_empty_uncles;
+    }
+
+    /// Sets when a canonical block is considered final. Can only be called by the contract itself.
+    pub fn set_finality_mode(&mut self, #[serializer(borsh)] finality_mode: FinalityMode) {
+        assert_self();
+        self.finality_mode = finality_mode;
+        // A higher threshold makes fewer blocks final, so the search starts over.
+        self.difficulty_safe_number = None;
+        self.advance_difficulty_finality();
+    }
+
+    /// Corrects the epoch of the first DAG merkle root of a client initialized with a wrong one.
+    /// Can only be called by the contract itself while the client is paused and before any header
+    /// after the first one is added.
+    pub fn set_dag_start_epoch(&mut self, #[serializer(borsh)] start: u64) {
+        assert_self();
+        assert!(
+            self.paused,
+            "The client should be paused to set the DAG start epoch"
+        );
+        assert!(
+            self.infos.len() == 1,
+            "The DAG start epoch can only be set before other headers are added"
+        );
+        self.dags_start_epoch = start;
+        env::log(format!("Set the DAG start epoch to {}", start).as_bytes());
+    }
+
+    /// Suspends adding new headers. Can only be called by the contract itself.
+    pub fn pause(&mut self) {
+        assert_self();
+        self.paused = true;
+    }
+
+    /// Resumes adding new headers. Can only be called by the contract itself.
+    pub fn unpause(&mut self) {
+        assert_self();
+        self.paused = false;
+    }
+
+    /// Replaces all DAG merkle roots and logs the justification together with the new commitment.
+    /// Can only be called by the contract itself while the client is paused.
+    pub fn replace_dag_roots(
+        &mut self,
+        #[serializer(borsh)] dags_start_epoch: u64,
+        #[serializer(borsh)] dags_merkle_roots: Vec<H128>,
+        #[serializer(borsh)] justification: String,
+    ) {
+        assert_self();
+        assert!(
+            self.paused,
+            "The client should be paused to replace the DAG merkle roots"
+        );
+        self.dags_start_epoch = dags_start_epoch;
+        self.dag_roots_hash = Self::hash_dag_roots(&dags_merkle_roots);
+        self.dags_merkle_roots = dags_merkle_roots;
+        env::log(
+            format!(
+                "Replaced the DAG merkle roots: {}. New commitment: {}",
+                justification,
+                hex::encode(&(self.dag_roots_hash.0).0)
+            )
+            .as_bytes(),
+        );
+    }
+
+    /// Drops all buffered headers with unknown parents. Can only be called by the contract
+    /// itself.
+    pub fn clear_orphans(&mut self) {
+        assert_self();
+        self.orphans.clear();
+    }
+
+    /// Records the buffered headers left by the previous calls because of
+    /// `max_orphan_reprocess_per_call`, again at most `max_orphan_reprocess_per_call` of them.
+    pub fn process_pending_orphans(&mut self) {
+        assert!(!self.paused, "The client is paused");
+        let mut parents: Vec<H256> = vec![];
+        for orphan in self.orphans.values() {
+            let parent_hash = rlp::decode::<BlockHeader>(&orphan).unwrap().parent_hash;
+            if !parents.contains(&parent_hash) && self.infos.get(&parent_hash).is_some() {
+                parents.push(parent_hash);
+            }
+        }
+        self.process_orphans(parents);
+    }
+
+    /// Allows the account to submit headers. Once at least one relayer is added, only the allowed
+    /// relayers can submit headers. Can only be called by the contract itself.
+    pub fn add_relayer(&mut self, #[serializer(borsh)] account_id: AccountId) {
+        assert_self();
+        self.relayers.insert(&account_id);
+    }
+
+    /// Disallows the account to submit headers. Removing the last relayer makes the client
+    /// permissionless again. Can only be called by the contract itself.
+    pub fn remove_relayer(&mut self, #[serializer(borsh)] account_id: AccountId) {
+        assert_self();
+        self.relayers.remove(&account_id);
+    }
+
+    /// Add the block header to the client.
+    /// `block_header` -- RLP-encoded Ethereum header;
+    /// `dag_nodes` -- dag nodes with their merkle proofs, not used in the clique mode.
+    #[result_serializer(borsh)]
+    pub fn add_block_header(
+        &mut self,
+        #[serializer(borsh)] block_header: Vec<u8>,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) {
+        let header: BlockHeader = rlp::decode(block_header.as_slice()).unwrap();
+        self.add_header_or_panic(header, &dag_nodes);
+    }
+
+    /// Same as `add_block_header`, but first checks that the hash of the header is
+    /// `expected_hash`, so that a relayer which disagrees with the client about the header hash
+    /// gets a clear error.
+    #[result_serializer(borsh)]
+    pub fn add_block_header_checked(
+        &mut self,
+        #[serializer(borsh)] block_header: Vec<u8>,
+        #[serializer(borsh)] expected_hash: H256,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) {
+        let header: BlockHeader = rlp::decode(block_header.as_slice()).unwrap();
+        assert_eq!(
+            header.hash.unwrap(),
+            expected_hash,
+            "Hash of the header {} is not the expected one",
+            header.number
+        );
+        self.add_header_or_panic(header, &dag_nodes);
+    }
+
+    /// Same as `add_block_header`, but returns one of the `ADD_HEADER_*` status codes instead of
+    /// panicking if the header has an unknown parent, is already known, is stale or is invalid,
+    /// including undecodable RLP, so that the calling contracts don't have to parse panic
+    /// messages. Submissions that are not allowed at all, e.g. while the client is paused, still
+    /// panic. Like a panic, any other status than `ADD_HEADER_OK` returns the attached deposit.
+    #[result_serializer(borsh)]
+    pub fn add_block_header_result(
+        &mut self,
+        #[serializer(borsh)] block_header: Vec<u8>,
+        #[serializer(borsh)] dag_nodes: Vec<DoubleNodeWithMerkleProof>,
+    ) -> u8 {
+        let code = match rlp::decode(block_header.as_slice()) {
+            Ok(header) => match self.add_header(header, &dag_nodes) {
+                Ok(()) => ADD_HEADER_OK,
+                Err(rejection) => rejection.code,
+            },
+            Err(_) => ADD_HEADER_INVALID,
+        };
+        if code != ADD_HEADER_OK {
+            Self::refund_deposit();
+        }
+        code
+    }
+
+    /// Same as `add_block_header`, but takes the dag nodes packed by
+    /// `DoubleNodeWithMerkleProof::pack`, which is shorter than their borsh serialization.
+    #[result_serializer(borsh)]
+    pub fn add_block_header_compact(
+        &mut self,
+        #[serializer(borsh)] block_header: Vec<u8>,
+        #[serializer(borsh)] packed_dag_nodes: Vec<u8>,
+    ) {
+        let header: BlockHeader = rlp::decode(block_header.as_slice()).unwrap();
+        let dag_nodes = DoubleNodeWithMerkleProof::unpack(&packed_dag_nodes);
+        self.add_header_or_panic(header, &dag_nodes);
+    }
+}
+
+impl EthClient {
+    /// Keccak256 of the concatenated DAG merkle roots.
+    fn hash_dag_roots(roots: &[H128]) -> H256 {
+        let data: Vec<u8> = roots
+            .iter()
+            .flat_map(|root| (root.0).0.iter().cloned())
+            .collect();
+        near_keccak256(&data).into()
+    }
+
+    /// Add the decoded header submitted by a relayer, panicking with the message of the rejection
+    /// if it is not added.
+    fn add_header_or_panic(
+        &mut self,
+        header: BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) {
+        if let Err(rejection) = self.add_header(header, dag_nodes) {
+            panic!("{}", rejection.message);
+        }
+    }
+
+    /// Returns the attached deposit to the submitter of a header that was rejected without a
+    /// panic.
+    fn refund_deposit() {
+        let deposit = env::attached_deposit();
+        if deposit > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(deposit);
+        }
+    }
+
+    /// Add the decoded header submitted by a relayer. Panics only if the submission is not allowed
+    /// at all.
+    fn add_header(
+        &mut self,
+        header: BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) -> Result<(), Rejection> {
+        assert!(!self.paused, "The client is paused");
+        assert!(
+            env::attached_deposit() >= self.min_submission_deposit,
+            "At least {} yoctoNEAR should be attached to submit a header",
+            self.min_submission_deposit
+        );
+        if !self.relayers.is_empty() {
+            assert!(
+                self.relayers.contains(&env::predecessor_account_id()),
+                "Only allowed relayers can add a new header"
+            );
+        }
+        self.last_submit_was_reorg = false;
+        if self.on_missing_parent == OnMissingParent::Buffer
+            && self.infos.get(&header.parent_hash).is_none()
+        {
+            return self.buffer_orphan(header, dag_nodes);
+        }
+        let pow_verified = self.admit_header(&header, dag_nodes)?;
+        let header_hash = header.hash.unwrap();
+        self.record_header(header, pow_verified);
+        self.process_orphans(vec![header_hash]);
+        Ok(())
+    }
+
+    /// The only gate for the new headers: fails unless the header is either valid or submitted by
+    /// the trusted signer, and can be recorded. Returns whether the PoW of the header was verified.
+    fn admit_header(
+        &mut self,
+        header: &BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) -> Result<bool, Rejection> {
+        let trusted = self.is_trusted_mode();
+        if self.infos.get(&header.hash.unwrap()).is_some() {
+            return Err(Rejection::new(
+                ADD_HEADER_KNOWN,
+                format!("Header is already known. Number: {}", header.number),
+            ));
+        }
+        let prev = self.stored_header(&header.parent_hash).ok_or_else(|| {
+            Rejection::new(
+                ADD_HEADER_UNKNOWN_PARENT,
+                "Parent header should be present to add a new header".to_string(),
+            )
+        })?;
+        if header.number + self.finalized_gc_threshold < self.last_block_number() {
+            return Err(Rejection::new(
+                ADD_HEADER_STALE,
+                "Header is too old to have a chance to appear on the canonical chain.".to_string(),
+            ));
+        }
+        let invalid = |message| Rejection::new(ADD_HEADER_INVALID, message);
+        // A header that can't be valid is reported as such before it is checked against the
+        // recorded ones.
+        if !trusted {
+            Self::check_difficulty_nonzero(header).map_err(invalid)?;
+        }
+        self.check_record(header).map_err(invalid)?;
+        if trusted {
+            return Ok(false);
+        }
+        self.verify_header(header, &prev, dag_nodes)
+            .map_err(invalid)?;
+        self.count_pow_verification();
+        Ok(self.seal_verified())
+    }
+
+    /// Returns whether the client is in the trusted mode. Panics if it is, but the header is not
+    /// submitted by the trusted signer.
+    fn is_trusted_mode(&self) -> bool {
+        match &self.trusted_signer {
+            Some(trusted_signer) => {
+                assert!(
+                    &env::signer_account_id() == trusted_signer,
+                    "Eth-client is deployed as trust mode, only trusted_signer can add a new header"
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Counts the verification of the seal of a submitted header if the seal is an ethash PoW
+    /// checked against the DAG merkle roots.
+    fn count_pow_verification(&mut self) {
+        if self.consensus == ConsensusMode::Ethash && self.validate_ethash {
+            self.pow_verifications += 1;
+        }
+    }
+
+    /// Returns whether a successful verification of the seal amounts to a verified PoW.
+    fn seal_verified(&self) -> bool {
+        match self.consensus {
+            // Without ethash validation the DAG nodes are not checked against the merkle roots.
+            ConsensusMode::Ethash => self.validate_ethash,
+            ConsensusMode::Clique { .. } => true,
+        }
+    }
+
+    /// Buffer the header whose parent is unknown. Only the seal can be verified without the
+    /// parent, the rest of the header is verified once the parent is added.
+    fn buffer_orphan(
+        &mut self,
+        header: BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) -> Result<(), Rejection> {
+        let header_hash = header.hash.unwrap();
+        if self.orphans.get(&header_hash).is_some() {
+            return Err(Rejection::new(
+                ADD_HEADER_KNOWN,
+                format!("Header {} is already buffered", header.number),
+            ));
+        }
+        if header.number + self.finalized_gc_threshold < self.last_block_number() {
+            return Err(Rejection::new(
+                ADD_HEADER_STALE,
+                "Header is too old to have a chance to appear on the canonical chain.".to_string(),
+            ));
+        }
+        if !self.is_trusted_mode() {
+            // Checked before buffering, so that the orphan can't make the submission of its parent
+            // fail.
+            Self::check_gas_limit_nonzero(&header)
+                .and_then(|()| self.verify_seal(&header, dag_nodes))
+                .map_err(|message| Rejection::new(ADD_HEADER_INVALID, message))?;
+            self.count_pow_verification();
+        }
+        if self.orphans.len() >= MAX_ORPHANS {
+            let lowest = self
+                .orphans
+                .iter()
+                .min_by_key(|(_, orphan)| rlp::decode::<BlockHeader>(orphan).unwrap().number)
+                .map(|(hash, _)| hash);
+            if let Some(hash) = lowest {
+                self.orphans.remove(&hash);
+            }
+        }
+        self.orphans.insert(&header_hash, &rlp::encode(&header));
+        Ok(())
+    }
+
+    /// Record the buffered descendants of the given headers. A descendant that can't be recorded
+    /// is dropped and its own descendants stay buffered. At most `max_orphan_reprocess_per_call`
+    /// descendants are recorded, the rest is left for `process_pending_orphans`.
+    fn process_orphans(&mut self, mut parents: Vec<H256>) {
+        let mut processed = 0;
+        while let Some(parent_hash) = parents.pop() {
+            let children: Vec<(H256, BlockHeader)> = self
+                .orphans
+                .iter()
+                .map(|(hash, orphan)| (hash, rlp::decode(&orphan).unwrap()))
+                .filter(|(_, header): &(H256, BlockHeader)| header.parent_hash == parent_hash)
+                .collect();
+            for (hash, header) in children {
+                if processed == self.max_orphan_reprocess_per_call {
+                    return;
+                }
+                processed += 1;
+                self.orphans.remove(&hash);
+                let prev = self.stored_header(&parent_hash).unwrap();
+                // The seal was verified when the header was buffered.
+                let pow_verified = if self.trusted_signer.is_some() {
+                    false
+                } else if self.verify_fields(&header, &prev).is_ok() {
+                    self.seal_verified()
+                } else {
+                    continue;
+                };
+                if self.check_record(&header).is_err() {
+                    continue;
+                }
+                self.record_header(header, pow_verified);
+                parents.push(hash);
+            }
+        }
+    }
+
+    /// Returns why the header can't be recorded, without changing the state. Only the relation
+    /// of the header to the recorded ones is checked, the header itself is verified separately.
+    fn check_record(&self, header: &BlockHeader) -> Result<(), String> {
+        let best_info = self.infos.get(&self.best_header_hash).unwrap();
+        if header.number + self.finalized_gc_threshold < best_info.number {
+            return Err(
+                "Header is too old to have a chance to appear on the canonical chain.".to_string(),
+            );
+        }
+        if header.timestamp < self.min_header_timestamp {
+            return Err(format!(
+                "Header {} is older than the minimum timestamp {}",
+                header.number, self.min_header_timestamp
+            ));
+        }
+        if header.number > best_info.number.saturating_add(self.max_header_gap) {
+            return Err(format!(
+                "Header {} is too far ahead of the best header {}",
+                header.number, best_info.number
+            ));
+        }
+        let parent_info = self
+            .infos
+            .get(&header.parent_hash)
+            .ok_or("Header has unknown parent. Parent should be submitted first.")?;
+        // The parent is looked up by hash, so a header whose number doesn't follow the one of its
+        // parent is rejected with a dedicated message, even in the trusted mode.
+        if parent_info.number.checked_add(1) != Some(header.number) {
+            return Err(format!(
+                "parent number mismatch: header {}, parent {}",
+                header.number, parent_info.number
+            ));
+        }
+        if self.infos.get(&header.hash.unwrap()).is_some() {
+            return Err(format!(
+                "Header is already known. Number: {}",
+                header.number
+            ));
+        }
+        let total_difficulty = parent_info
+            .total_difficulty
+            .0
+            .checked_add(header.difficulty.0)
+            .map(U256)
+            .ok_or("total difficulty overflow")?;
+        let extends_best = header.parent_hash == self.best_header_hash;
+        // Verified headers have a positive difficulty, so a header extending the best one always
+        // increases the total difficulty of the canonical chain. The difficulty of the headers of
+        // the trusted signer may be zero, such an extension is left to the tie-break.
+        if extends_best || !self.wins_best(header, total_difficulty, &best_info) {
+            return Ok(());
+        }
+
+        // The branch replaces the canonical blocks above its fork point. The headers of the trusted
+        // signer are not verified, so the timestamps along the branch are checked here.
+        let mut child_timestamp = header.timestamp;
+        let mut hash = header.parent_hash;
+        let fork_point = loop {
+            let info = self.infos.get(&hash).unwrap();
+            if info.timestamp >= child_timestamp {
+                return Err(format!(
+                    "Header {} of the adopted branch is not newer than its parent",
+                    info.number + 1
+                ));
+            }
+            if info.number == 0 || self.canonical_header_hashes.get(&info.number) == Some(hash) {
+                break info.number;
+            }
+            if self.infos.get(&info.parent_hash).is_none() {
+                break info.number - 1;
+            }
+            child_timestamp = info.timestamp;
+            hash = info.parent_hash;
+        };
+        // Final blocks are relied upon by the applications, so the canonical chain must never
+        // drop them.
+        match self.safe_block_number() {
+            Some(safe_block_number) if fork_point < safe_block_number => Err(format!(
+                "Reorg to header {} would remove final blocks",
+                header.number
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether the header with the given total difficulty replaces the best header.
+    fn wins_best(
+        &self,
+        header: &BlockHeader,
+        total_difficulty: U256,
+        best_info: &HeaderInfo,
+    ) -> bool {
+        let wins_tie = total_difficulty == best_info.total_difficulty
+            && match self.tie_break {
+                TieBreak::EvenDifficulty => header.difficulty % 2 == U256::default(),
+                TieBreak::HigherNumber => header.number > best_info.number,
+            };
+        total_difficulty > best_info.total_difficulty || wins_tie
+    }
+
+    /// Record the header, which has to pass `check_record` first. If needed update the canonical
+    /// chain and perform the GC. `pow_verified` tells whether the PoW of the header was verified
+    /// before recording it.
+    fn record_header(&mut self, header: BlockHeader, pow_verified: bool) {
+        // Headers with unverified PoW are only expected from the clients which do not verify it.
+        #[cfg(feature = "invariant_checks")]
+        assert!(
+            pow_verified || self.trusted_signer.is_some() || !self.validate_ethash,
+            "Header {} was not verified before recording it",
+            header.number
+        );
+        let best_info = self.infos.get(&self.best_header_hash).unwrap();
+        let header_hash = header.hash.unwrap();
+        let header_number = header.number;
+        let parent_info = self.infos.get(&header.parent_hash).unwrap();
+
+        // Record this header in `all_hashes`.
+        let mut all_hashes = self
+            .all_header_hashes
+            .get(&header_number)
+            .unwrap_or_default();
+        all_hashes.push(header_hash);
+        self.all_header_hashes.insert(&header_number, &all_hashes);
+
+        // Record full information about this header.
+        if self.store_full_headers {
+            self.headers.insert(&header_hash, &header);
+        } else {
+            self.compact_headers
+                .insert(&header_hash, &CompactHeader::from_header(&header));
+        }
+        let total_difficulty = parent_info
+            .total_difficulty
+            .0
+            .checked_add(header.difficulty.0)
+            .map(U256)
+            .unwrap();
+        let info = HeaderInfo {
+            total_difficulty,
+            parent_hash: header.parent_hash.clone(),
+            number: header_number,
+            difficulty: header.difficulty,
+            timestamp: header.timestamp,
+            pow_verified,
+        };
+        self.infos.insert(&header_hash, &info);
+        // The number of the stored info and of the stored header must both be the one of the
+        // submitted header.
+        #[cfg(feature = "invariant_checks")]
+        assert!(
+            info.number == header.number
+                && self.stored_header(&header_hash).map(|stored| stored.number)
+                    == Some(header.number),
+            "Stored header {} does not match its info",
+            header.number
+        );
+        self.last_update_block_timestamp = env::block_timestamp();
+        let epoch = header_number / EPOCH_LENGTH;
+        let submissions = self.epoch_submission_count(epoch);
+        self.epoch_submissions.insert(&epoch, &(submissions + 1));
+
+        // Check if canonical chain needs to be updated.
+        if self.wins_best(&header, info.total_difficulty, &best_info) {
+            // If the new header has a lower number than the previous header, we need to clean it
+            // going forward.
+            if best_info.number > info.number {
+                for number in info.number + 1..=best_info.number {
+                    self.canonical_header_hashes.remove(&number);
+                }
+            }
+            let is_reorg = info.parent_hash != self.best_header_hash;
+            if is_reorg {
+                self.reorg_count += 1;
+                self.last_submit_was_reorg = true;
+            }
+            let old_tip = self.best_header_hash;
+            // Replacing the global best header hash.
+            self.best_header_hash = header_hash;
+            self.canonical_header_hashes
+                .insert(&header_number, &header_hash);
+
+            // Replacing past hashes until we converge into the same parent.
+            // Starting from the parent hash.
+            let mut number = header.number - 1;
+            let mut current_hash = info.parent_hash;
+            loop {
+                let prev_value = self.canonical_header_hashes.insert(&number, &current_hash);
+                let current_info = self.infos.get(&current_hash);
+                // If the current block hash is 0 (unlikely), or the previous hash matches the
+                // current hash, then the chains converged and we can stop now.
+                if number == 0 || prev_value == Some(current_hash) {
+                    break;
+                }
+                // Check if there is an info to get the parent hash
+                if let Some(info) = current_info {
+                    current_hash = info.parent_hash;
+                } else {
+                    break;
+                }
+                number -= 1;
+            }
+            self.lowest_canonical_number = std::cmp::min(self.lowest_canonical_number, number);
+            if is_reorg {
+                self.record_reorg_event(ReorgEvent {
+                    at_block: number,
+                    old_tip,
+                    new_tip: header_hash,
+                    depth: best_info.number.saturating_sub(number),
+                });
+            }
+            // A straight extension must leave the canonical chain contiguous up to the new best
+            // header.
+            #[cfg(feature = "invariant_checks")]
+            assert!(
+                is_reorg
+                    || header_number == 0
+                    || self.canonical_header_hashes.get(&(header_number - 1))
+                        == Some(info.parent_hash),
+                "Canonical chain has a gap below the header {}",
+                header_number
+            );
+            self.advance_difficulty_finality();
+            self.maybe_gc(header_number);
+        }
+    }
+
+    /// Perform the GC for the given best header number. Removes at most `max_gc_per_call`
+    /// numbers from each of the collections, the rest is removed by the following calls.
+    fn maybe_gc(&mut self, best_number: u64) {
+        // Blocks that are not final can still be reorganized, so they are kept regardless of the
+        // thresholds.
+        let safe_block_number = match self.safe_block_number() {
+            Some(safe_block_number) => safe_block_number,
+            None => return,
+        };
+        // Nothing is old enough to be removed while the chain is shorter than the thresholds.
+        if let Some(header_number) = best_number.checked_sub(self.hashes_gc_threshold) {
+            self.gc_canonical_chain(std::cmp::min(header_number, safe_block_number));
+        }
+        if let Some(header_number) = best_number.checked_sub(self.finalized_gc_threshold) {
+            self.gc_headers(std::cmp::min(header_number, safe_block_number));
+        }
+    }
+
+    /// Advances `difficulty_safe_number` over the canonical blocks that became final, checking at
+    /// most `MAX_QUERY_LENGTH` blocks. Reorgs never remove final blocks, so it only moves forward.
+    fn advance_difficulty_finality(&mut self) {
+        let threshold = match &self.finality_mode {
+            FinalityMode::Difficulty(threshold) => threshold.clone(),
+            FinalityMode::FixedDepth(_) => return,
+        };
+        let best_info = self.infos.get(&self.best_header_hash).unwrap();
+        let mut number = match self.difficulty_safe_number {
+            Some(safe_block_number) => safe_block_number + 1,
+            None => std::cmp::max(self.earliest_available_block(), self.lowest_header_number),
+        };
+        for _ in 0..MAX_QUERY_LENGTH {
+            let info = match self
+                .canonical_header_hashes
+                .get(&number)
+                .and_then(|hash| self.infos.get(&hash))
+            {
+                Some(info) => info,
+                None => break,
+            };
+            if best_info.total_difficulty - info.total_difficulty < threshold {
+                break;
+            }
+            self.difficulty_safe_number = Some(number);
+            number += 1;
+        }
+    }
+
+    /// Remove hashes from the canonical chain that are at least as old as the given header number.
+    /// The oldest hashes are removed first.
+    fn gc_canonical_chain(&mut self, header_number: u64) {
+        let numbers = self.canonical_gc_range(header_number);
+        let stop = numbers.end;
+        for number in numbers {
+            self.canonical_header_hashes.remove(&number);
+        }
+        self.lowest_canonical_number = std::cmp::max(self.lowest_canonical_number, stop);
+    }
+
+    /// Numbers of the blocks of the epoch between `earliest_available_block` and the best header.
+    fn retained_numbers_in_epoch(&self, epoch: u64) -> std::ops::RangeInclusive<u64> {
+        let first = std::cmp::max(
+            epoch.saturating_mul(EPOCH_LENGTH),
+            self.earliest_available_block(),
+        );
+        let last = std::cmp::min(
+            epoch.saturating_add(1).saturating_mul(EPOCH_LENGTH) - 1,
+            self.last_block_number(),
+        );
+        first..=last
+    }
+
+    /// Numbers removed from the canonical chain by `gc_canonical_chain`.
+    fn canonical_gc_range(&self, header_number: u64) -> std::ops::Range<u64> {
+        let start = self.earliest_available_block();
+        let stop = std::cmp::min(header_number + 1, start + self.max_gc_per_call);
+        start..stop
+    }
+
+    /// Returns the stored header, with the fields dropped by `CompactHeader` left empty if it is
+    /// stored compactly.
+    fn stored_header(&self, hash: &H256) -> Option<BlockHeader> {
+        self.headers.get(hash).or_else(|| {
+            self.compact_headers
+                .get(hash)
+                .map(CompactHeader::into_header)
+        })
+    }
+
+    /// Returns whether a stored header has the header with the given hash and number as its
+    /// parent.
+    fn has_child(&self, hash: &H256, number: u64) -> bool {
+        self.all_header_hashes
+            .get(&(number + 1))
+            .unwrap_or_default()
+            .iter()
+            .any(|child| {
+                self.infos
+                    .get(child)
+                    .map_or(false, |info| &info.parent_hash == hash)
+            })
+    }
+
+    /// Removes the header and its information, however the header is stored.
+    fn remove_header(&mut self, hash: &H256) {
+        self.headers.remove(hash);
+        self.compact_headers.remove(hash);
+        self.infos.remove(hash);
+    }
+
+    /// Remove information about the headers that are at least as old as the given header number.
+    /// The oldest headers are removed first.
+    fn gc_headers(&mut self, header_number: u64) {
+        let numbers = self.headers_gc_range(header_number);
+        let stop = numbers.end;
+        for number in numbers {
+            if let Some(all_headers) = self.all_header_hashes.get(&number) {
+                for hash in all_headers {
+                    self.remove_header(&hash);
+                }
+                self.all_header_hashes.remove(&number);
+            }
+        }
+        self.lowest_header_number = std::cmp::max(self.lowest_header_number, stop);
+    }
+
+    /// Numbers whose headers are removed by `gc_headers`.
+    fn headers_gc_range(&self, header_number: u64) -> std::ops::Range<u64> {
+        let start = self.lowest_header_number;
+        let stop = std::cmp::min(header_number + 1, start + self.max_gc_per_call);
+        start..stop
+    }
+
+    /// Verify PoW of the header.
+    fn verify_header(
+        &self,
+        header: &BlockHeader,
+        prev: &BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) -> Result<(), String> {
+        //
+        // See YellowPaper formula (50) in section 4.3.4
+        // 1. Difficulty is recomputed from the parent, including the difficulty bomb delays
+        // 2. Added condition: header.parent_hash() == prev.hash()
+        //
+        self.verify_seal(header, dag_nodes)?;
+        self.verify_fields(header, prev)
+    }
+
+    /// Verify the fields of the header against its parent.
+    fn verify_fields(&self, header: &BlockHeader, prev: &BlockHeader) -> Result<(), String> {
+        Self::check_gas_limit_nonzero(prev)?;
+        Self::check_gas_limit_nonzero(header)?;
+        let difficulty_ok = match self.consensus {
+            ConsensusMode::Ethash if self.validate_ethash => {
+                let expected = Self::expected_difficulty(header, prev).0;
+                let tolerance = expected * self.difficulty_tolerance_permille / 1000;
+                header.difficulty.0 >= expected.saturating_sub(tolerance)
+                    && header.difficulty.0 <= expected.saturating_add(tolerance)
+            }
+            _ => true,
+        };
+        let valid = difficulty_ok
+            && header.gas_used <= header.gas_limit
+            && header.gas_limit < prev.gas_limit * 1025 / 1024
+            && header.gas_limit > prev.gas_limit * 1023 / 1024
+            && header.gas_limit >= U256(5000.into())
+            && header.timestamp > prev.timestamp
+            && header.number == prev.number + 1
+            && header.parent_hash == prev.hash.unwrap()
+            && header.extra_data.len() as u64 <= self.max_extra_data_bytes
+            && (!self.require_empty_uncles || header.uncles_hash == Self::empty_uncles_hash());
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("The new header {} should be valid", header.number))
+        }
+    }
+
+    /// Keeps the reorg, dropping the oldest one once `MAX_REORG_EVENTS` are kept.
+    fn record_reorg_event(&mut self, event: ReorgEvent) {
+        if self.reorg_events.len() == MAX_REORG_EVENTS {
+            self.reorg_events.remove(0);
+        }
+        self.reorg_events.push(event);
+    }
+
+    /// The relative gas limit checks degenerate for a zero gas limit, so such headers are rejected
+    /// explicitly.
+    fn check_gas_limit_nonzero(header: &BlockHeader) -> Result<(), String> {
+        if header.gas_limit > U256::default() {
+            Ok(())
+        } else {
+            Err(format!("Header {} has zero gas limit", header.number))
+        }
+    }
+
+    /// Neither ethash nor clique headers have a zero difficulty, and the ethash boundary and
+    /// difficulty checks degenerate for it.
+    fn check_difficulty_nonzero(header: &BlockHeader) -> Result<(), String> {
+        if header.difficulty > U256::default() {
+            Ok(())
+        } else {
+            Err(format!("Header {} has zero difficulty", header.number))
+        }
+    }
+
+    /// Verify the seal of the header according to the consensus mode.
+    fn verify_seal(
+        &self,
+        header: &BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) -> Result<(), String> {
+        Self::check_difficulty_nonzero(header)?;
+        match &self.consensus {
+            ConsensusMode::Ethash => self.verify_pow(header, dag_nodes),
+            ConsensusMode::Clique { signers } => match Self::clique_signer(header) {
+                Some(signer) if signers.contains(&signer) => Ok(()),
+                _ => Err(format!("The new header {} should be valid", header.number)),
+            },
+        }
+    }
+
+    /// Uncles hash of the headers without uncles: the hash of the empty RLP list.
+    fn empty_uncles_hash() -> H256 {
+        near_keccak256(&rlp::EMPTY_LIST_RLP).into()
+    }
+
+    /// Difficulty of the ethash header expected on mainnet from its parent. See YellowPaper
+    /// formulas (41)-(46), amended by EIP-2 and EIP-100, with the bomb delayed by `BOMB_DELAYS`.
+    fn expected_difficulty(header: &BlockHeader, prev: &BlockHeader) -> U256 {
+        let parent_difficulty = prev.difficulty.0;
+        let step = parent_difficulty / 2048;
+        let elapsed = header.timestamp.saturating_sub(prev.timestamp);
+        let adjustment: i64 = if header.number < HOMESTEAD_BLOCK {
+            if elapsed < 13 {
+                1
+            } else {
+                -1
+            }
+        } else {
+            let (sigma, period) = if header.number < BYZANTIUM_BLOCK {
+                (1, 10)
+            } else if prev.uncles_hash == Self::empty_uncles_hash() {
+                (1, 9)
+            } else {
+                (2, 9)
+            };
+            std::cmp::max(sigma - (elapsed / period).min(100) as i64, -99)
+        };
+
+        let mut difficulty = if adjustment >= 0 {
+            parent_difficulty + step * adjustment as u64
+        } else {
+            parent_difficulty - step * (-adjustment) as u64
+        };
+        difficulty = std::cmp::max(difficulty, MIN_DIFFICULTY.into());
+
+        let delay = BOMB_DELAYS
+            .iter()
+            .find(|(fork, _)| header.number >= *fork)
+            .map_or(0, |(_, delay)| *delay);
+        let bomb_period = header.number.saturating_sub(delay) / 100_000;
+        if bomb_period > 1 {
+            difficulty = difficulty + (U256(1.into()).0 << (bomb_period - 2) as usize);
+        }
+        U256(difficulty)
+    }
+
+    /// Recover the signer of the clique header from the signature at the end of its extra data.
+    /// The signed message is the hash of the header without the signature.
+    fn clique_signer(header: &BlockHeader) -> Option<Address> {
+        let seal_start = header.extra_data.len().checked_sub(CLIQUE_SEAL_LENGTH)?;
+        let seal = &header.extra_data[seal_start..];
+        let mut unsealed = header.clone();
+        unsealed.extra_data.truncate(seal_start);
+        let message = secp256k1::Message::parse(&near_keccak256(&rlp::encode(&unsealed)));
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&seal[..64]);
+        let signature = secp256k1::Signature::parse(&signature);
+        let recovery_id = secp256k1::RecoveryId::parse(seal[64]).ok()?;
+        let public_key = secp256k1::recover(&message, &signature, &recovery_id).ok()?;
+        Some(Address::from(
+            &near_keccak256(&public_key.serialize()[1..])[12..],
+        ))
+    }
+
+    /// Verify that the ethash result of the header is below the boundary set by its difficulty.
+    fn verify_pow(
+        &self,
+        header: &BlockHeader,
+        dag_nodes: &[DoubleNodeWithMerkleProof],
+    ) -> Result<(), String> {
+        let (_mix_hash, result) = self.hashimoto_merkle(
+            &header.partial_hash.unwrap(),
+            &header.nonce,
+            header.number,
+            dag_nodes,
+        )?;
+        if U256((result.0).0.into()) < U256(ethash::cross_boundary(header.difficulty.0)) {
+            Ok(())
+        } else {
+            Err(format!("The new header {} should be valid", header.number))
+        }
+    }
+
+    /// Returns the DAG merkle root of the epoch, or why there is none.
+    fn find_dag_merkle_root(&self, epoch: u64) -> Result<H128, String> {
+        if self.dags_merkle_roots.is_empty() {
+            return Err("No DAG merkle roots are configured".to_string());
+        }
+        let offset = epoch
+            .checked_sub(self.dags_start_epoch)
+            .ok_or("Epoch is before the first DAG merkle root")?;
+        self.dags_merkle_roots
+            .get(offset as usize)
+            .cloned()
+            .ok_or_else(|| format!("No DAG merkle root for the epoch {}", epoch))
+    }
+
+    /// Verify merkle paths to the DAG nodes.
     fn hashimoto_merkle(
         &self,
         header_hash: &H256,
         nonce: &H64,
         header_number: u64,
         nodes: &[DoubleNodeWithMerkleProof],
-    ) -> (H256, H256) {
+    ) -> Result<(H256, H256), String> {
         // Boxed index since ethash::hashimoto gets Fn, but not FnMut
         let index = std::cell::RefCell::new(0);
+        let epoch = header_number / EPOCH_LENGTH;
 
-        // Reuse single Merkle root across all the proofs
-        let merkle_root = self.dag_merkle_root((header_number as usize / 30000) as u64);
+        // Reuse single Merkle root across all the proofs: every node has to be proven against the
+        // root of the epoch of the header, so a proof made for another epoch fails at its node.
+        let verified = if self.validate_ethash {
+            Some(VerifiedNodes::new(self.find_dag_merkle_root(epoch)?))
+        } else {
+            None
+        };
+        let verified = std::cell::RefCell::new(verified);
+        // The hasher can't return early, so the first failure is kept until hashimoto is done.
+        let error = std::cell::RefCell::new(None);
 
         let pair = ethash::hashimoto_with_hasher(
             header_hash.0,
             nonce.0,
-            ethash::get_full_size(header_number as usize / 30000),
+            ethash::get_full_size(epoch as usize),
             |offset| {
                 let idx = *index.borrow_mut();
                 *index.borrow_mut() += 1;
 
                 // Each two nodes are packed into single 128 bytes with Merkle proof
-                let node = &nodes[idx / 2];
-                if idx % 2 == 0 && self.validate_ethash {
-                    // Divide by 2 to adjust offset for 64-byte words instead of 128-byte
-                    assert_eq!(merkle_root, node.apply_merkle_proof((offset / 2) as u64));
+                let node = match nodes.get(idx / 2).filter(|node| node.dag_nodes.len() == 2) {
+                    Some(node) => node,
+                    None => {
+                        error
+                            .borrow_mut()
+                            .get_or_insert_with(|| format!("dag node {} is missing", idx / 2));
+                        return [0u8; 64].into();
+                    }
+                };
+                if idx % 2 == 0 {
+                    if let Some(verified) = verified.borrow_mut().as_mut() {
+                        // Divide by 2 to adjust offset for 64-byte words instead of 128-byte
+                        if let Err(message) = verified.verify(idx / 2, (offset / 2) as u64, node) {
+                            error.borrow_mut().get_or_insert(message);
+                        }
+                    }
                 };
 
                 // Reverse each 32 bytes for ETHASH compatibility
@@ -417,6 +2746,9 @@ impl EthClient {
             near_keccak512,
         );
 
-        (H256(pair.0), H256(pair.1))
+        match error.into_inner() {
+            Some(message) => Err(message),
+            None => Ok((H256(pair.0), H256(pair.1))),
+        }
     }
 }
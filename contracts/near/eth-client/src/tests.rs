@@ -1,7 +1,12 @@
 use futures::future::join_all;
 use std::panic;
 
-use crate::{DoubleNodeWithMerkleProof, EthClient};
+use crate::{
+    BridgeStats, ConsensusMode, DoubleNodeWithMerkleProof, EthClient, FinalityMode,
+    OnMissingParent, ReorgEvent, TieBreak, VerifiedNodes, ADD_HEADER_INVALID, ADD_HEADER_KNOWN,
+    ADD_HEADER_OK, ADD_HEADER_STALE, ADD_HEADER_UNKNOWN_PARENT,
+};
+use borsh::BorshSerialize;
 use eth_types::*;
 use hex::FromHex;
 use rlp::RlpStream;
@@ -151,7 +156,7 @@ fn rlp_append<TX>(header: &Block<TX>, stream: &mut RlpStream) {
 
 // TESTS
 
-use near_sdk::{testing_env, MockedBlockchain, VMContext};
+use near_sdk::{env, testing_env, MockedBlockchain, VMContext};
 
 lazy_static! {
     static ref WEB3RS: web3::Web3<web3::transports::Http> = {
@@ -185,6 +190,13 @@ fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
     }
 }
 
+/// Context of a call made by the contract account itself.
+fn get_self_context() -> VMContext {
+    let mut context = get_context(vec![], false);
+    context.predecessor_account_id = context.current_account_id.clone();
+    context
+}
+
 fn get_blocks(
     web3rust: &web3::Web3<web3::transports::Http>,
     start: usize,
@@ -227,6 +239,54 @@ fn read_block_raw(filename: String) -> BlockWithProofsRaw {
     serde_json::from_reader(std::fs::File::open(std::path::Path::new(&filename)).unwrap()).unwrap()
 }
 
+fn read_header(number: u64) -> Vec<u8> {
+    let block = read_block(format!("./src/data/{}.json", number));
+    block.header_rlp.0
+}
+
+/// Initializes a bridge in trusted mode, where headers submitted by the signer skip validation.
+fn trusted_contract(first_header: Vec<u8>) -> EthClient {
+    EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first_header,
+        30,
+        10,
+        10,
+        Some("bob.near".to_string()),
+        vec![],
+    )
+}
+
+/// Builds a child of `parent` which is only acceptable by a bridge in trusted mode.
+/// `salt` goes into the extra data so that siblings get distinct hashes.
+fn fake_child(parent: &[u8], difficulty: u64, salt: u8) -> Vec<u8> {
+    let parent: BlockHeader = rlp::decode(parent).unwrap();
+    let mut header = parent.clone();
+    header.parent_hash = parent.hash.unwrap();
+    header.number = parent.number + 1;
+    header.timestamp = parent.timestamp + 1;
+    header.difficulty = U256(difficulty.into());
+    header.extra_data = vec![salt];
+    rlp::encode(&header)
+}
+
+/// Submits `len` trusted headers on top of `parent` and returns them.
+fn add_fake_chain(contract: &mut EthClient, parent: &[u8], len: usize) -> Vec<Vec<u8>> {
+    let mut headers: Vec<Vec<u8>> = vec![];
+    for _ in 0..len {
+        let header = fake_child(headers.last().map_or(parent, |h| h.as_slice()), 10, 0);
+        contract.add_block_header(header.clone(), vec![]);
+        headers.push(header);
+    }
+    headers
+}
+
+fn hash_of(header: &[u8]) -> H256 {
+    rlp::decode::<BlockHeader>(header).unwrap().hash.unwrap()
+}
+
 #[test]
 fn add_dags_merkle_roots() {
     testing_env!(get_context(vec![], false));
@@ -242,6 +302,7 @@ fn add_dags_merkle_roots() {
         10,
         10,
         None,
+        vec![],
     );
 
     assert_eq!(dmr.dag_merkle_roots[0], contract.dag_merkle_root(0));
@@ -274,6 +335,7 @@ fn add_blocks_2_and_3() {
         10,
         10,
         None,
+        vec![],
     );
 
     for (block, proof) in blocks
@@ -310,6 +372,7 @@ fn add_400000_block_only() {
         10,
         10,
         None,
+        vec![],
     );
     assert_eq!((hashes[0].0).0, (contract.block_hash(400_000).unwrap().0).0);
 }
@@ -337,6 +400,7 @@ fn add_two_blocks_from_8996776() {
         10,
         10,
         None,
+        vec![],
     );
 
     for (block, proof) in blocks
@@ -385,6 +449,7 @@ fn add_2_blocks_from_400000() {
         10,
         10,
         None,
+        vec![],
     );
 
     for (block, proof) in blocks
@@ -440,6 +505,7 @@ fn predumped_block_can_be_added() {
         10,
         10,
         None,
+        vec![],
     );
 
     let bar = ProgressBar::new(blocks_with_proofs.len() as _);
@@ -461,3 +527,2713 @@ fn predumped_block_can_be_added() {
     }
     bar.finish();
 }
+
+#[test]
+#[should_panic(expected = "total difficulty overflow")]
+fn total_difficulty_overflow_panics() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let first_hash = hash_of(&first);
+    let mut contract = trusted_contract(first.clone());
+
+    let mut info = contract.infos.get(&first_hash).unwrap();
+    info.total_difficulty = U256(!U256::default().0);
+    contract.infos.insert(&first_hash, &info);
+
+    contract.add_block_header(fake_child(&first, 1, 0), vec![]);
+}
+
+#[test]
+fn is_ancestor_follows_parents() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let a = fake_child(&first, 10, 0);
+    let b = fake_child(&a, 10, 0);
+    let fork = fake_child(&first, 5, 1);
+    for header in vec![a.clone(), b.clone(), fork.clone()] {
+        contract.add_block_header(header, vec![]);
+    }
+
+    assert!(contract.is_ancestor(hash_of(&first), hash_of(&b), 10));
+    assert!(contract.is_ancestor(hash_of(&a), hash_of(&b), 1));
+    assert!(!contract.is_ancestor(hash_of(&first), hash_of(&b), 1));
+    assert!(!contract.is_ancestor(hash_of(&fork), hash_of(&b), 10));
+}
+
+#[test]
+fn safe_block_number_across_confirmations() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 8;
+    let mut header = rlp::encode(&first);
+    let mut contract = trusted_contract(header.clone());
+    assert_eq!(contract.safe_block_number(), None);
+
+    header = fake_child(&header, 10, 0);
+    contract.add_block_header(header.clone(), vec![]);
+    assert_eq!(contract.safe_block_number(), None);
+
+    header = fake_child(&header, 10, 0);
+    contract.add_block_header(header.clone(), vec![]);
+    assert_eq!(contract.safe_block_number(), Some(0));
+    assert!(contract.block_hash_safe(10).is_none());
+}
+
+#[test]
+fn confirm_total_difficulty_claims() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let a = fake_child(&first, 10, 0);
+    let b = fake_child(&a, 15, 0);
+    contract.add_block_header(a.clone(), vec![]);
+    contract.add_block_header(b.clone(), vec![]);
+
+    assert!(contract.confirm_total_difficulty(hash_of(&b), U256(25.into())));
+    assert!(!contract.confirm_total_difficulty(hash_of(&b), U256(15.into())));
+    assert!(!contract.confirm_total_difficulty(hash_of(&fake_child(&b, 1, 0)), U256(26.into())));
+}
+
+#[test]
+fn permissionless_without_relayers() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let header = fake_child(&first, 10, 0);
+    contract.add_block_header(header.clone(), vec![]);
+    assert_eq!(
+        contract.block_hash(contract.last_block_number()),
+        Some(hash_of(&header))
+    );
+}
+
+#[test]
+fn allowed_relayer_can_submit() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.add_relayer("alice.near".to_string());
+    let header = fake_child(&first, 10, 0);
+    contract.add_block_header(header.clone(), vec![]);
+    assert_eq!(
+        contract.block_hash(contract.last_block_number()),
+        Some(hash_of(&header))
+    );
+}
+
+#[test]
+#[should_panic(expected = "Only allowed relayers can add a new header")]
+fn disallowed_relayer_is_rejected() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.add_relayer("dave.near".to_string());
+    contract.add_relayer("alice.near".to_string());
+    contract.remove_relayer("alice.near".to_string());
+    contract.add_block_header(fake_child(&first, 10, 0), vec![]);
+}
+
+#[test]
+fn dag_access_indices_match_proofs() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234002.json".to_string());
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+    let indices = contract.dag_access_indices(block.header_rlp.0.clone(), dag_nodes.clone());
+
+    assert_eq!(indices.len(), dag_nodes.len() * 2);
+    for (i, node) in dag_nodes.iter().enumerate() {
+        // Both halves of a node are fetched one after another.
+        assert_eq!(indices[2 * i + 1], indices[2 * i] + 1);
+        assert_eq!(
+            node.apply_merkle_proof(indices[2 * i] / 2),
+            contract.dag_merkle_root(10_234_002 / 30000)
+        );
+    }
+}
+
+#[test]
+fn dag_access_indices_of_malformed_header_are_empty() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234002.json".to_string());
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let header = block.header_rlp.0.clone();
+    let truncated = header[..header.len() - 1].to_vec();
+
+    assert_eq!(
+        contract.dag_access_indices(truncated, block.to_double_node_with_merkle_proof_vec()),
+        Vec::<u64>::new()
+    );
+}
+
+#[test]
+fn prefixed_clients_do_not_collide() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let init = |prefix: &[u8]| {
+        EthClient::init(
+            true,
+            0,
+            vec![],
+            first.clone(),
+            30,
+            10,
+            10,
+            Some("bob.near".to_string()),
+            prefix.to_vec(),
+        )
+    };
+    let mut mainnet = init(b"mainnet");
+    let testnet = init(b"testnet");
+
+    let header = fake_child(&first, 10, 0);
+    mainnet.add_block_header(header.clone(), vec![]);
+
+    assert!(mainnet.headers.get(&hash_of(&header)).is_some());
+    assert!(testnet.headers.get(&hash_of(&header)).is_none());
+    assert_eq!(testnet.last_block_number(), 10_234_001);
+    assert_eq!(testnet.block_hash(10_234_002), None);
+}
+
+#[test]
+fn verify_block_binding() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let header = fake_child(&first, 10, 0);
+    let fork = fake_child(&first, 5, 1);
+    contract.add_block_header(header.clone(), vec![]);
+    contract.add_block_header(fork.clone(), vec![]);
+
+    assert!(contract.verify_block(10_234_001, hash_of(&first)));
+    assert!(contract.verify_block(10_234_002, hash_of(&header)));
+    assert!(!contract.verify_block(10_234_002, hash_of(&fork)));
+    assert!(!contract.verify_block(10_234_001, hash_of(&header)));
+}
+
+#[test]
+fn number_of_pruned_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 5);
+    assert_eq!(contract.number_of(hash_of(&first)), Some(10_234_001));
+    assert_eq!(contract.number_of(hash_of(&headers[4])), Some(10_234_006));
+
+    add_fake_chain(&mut contract, &headers[4], 7);
+    assert_eq!(contract.number_of(hash_of(&first)), None);
+    assert_eq!(contract.number_of(hash_of(&headers[4])), Some(10_234_006));
+}
+
+#[test]
+#[should_panic(expected = "has zero difficulty")]
+fn zero_difficulty_header_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let mut header: BlockHeader = rlp::decode(&block.header_rlp.0).unwrap();
+    header.difficulty = U256::default();
+    contract.add_block_header(
+        rlp::encode(&header),
+        block.to_double_node_with_merkle_proof_vec(),
+    );
+}
+
+#[test]
+fn dag_config_matches_init() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/400000.json".to_string());
+    let contract = EthClient::init(
+        true,
+        400_000 / 30000,
+        vec![block.merkle_root],
+        block.header_rlp.0,
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    assert_eq!(contract.dag_config(), (13, 30000));
+}
+
+#[test]
+fn attach_pow_proof_to_first_header() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234001.json".to_string());
+    let mut contract = trusted_contract(block.header_rlp.0.clone());
+    let hash = hash_of(&block.header_rlp.0);
+    assert!(!contract.infos.get(&hash).unwrap().pow_verified);
+
+    contract.attach_pow_proof(10_234_001, block.to_double_node_with_merkle_proof_vec());
+    assert!(contract.infos.get(&hash).unwrap().pow_verified);
+}
+
+#[test]
+fn pow_verified_only_for_verified_headers() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let block = read_block("./src/data/10234002.json".to_string());
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    contract.add_block_header(
+        block.header_rlp.0.clone(),
+        block.to_double_node_with_merkle_proof_vec(),
+    );
+
+    assert_eq!(contract.is_pow_verified(hash_of(&first)), Some(false));
+    assert_eq!(
+        contract.is_pow_verified(hash_of(&block.header_rlp.0)),
+        Some(true)
+    );
+    assert_eq!(contract.is_pow_verified(H256::default()), None);
+}
+
+#[test]
+fn stats_after_reorg_and_gc() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 3);
+    let fork = fake_child(&headers[0], 100, 1);
+    contract.add_block_header(fork.clone(), vec![]);
+    assert_eq!(
+        contract.stats(),
+        BridgeStats {
+            headers_count: 5,
+            canonical_count: 3,
+            reorg_count: 1,
+            earliest_available_block: 10_234_001,
+        }
+    );
+
+    add_fake_chain(&mut contract, &fork, 35);
+    assert_eq!(
+        contract.stats(),
+        BridgeStats {
+            headers_count: 10,
+            canonical_count: 30,
+            reorg_count: 1,
+            earliest_available_block: 10_234_009,
+        }
+    );
+}
+
+#[test]
+fn recompute_total_difficulty_of_clean_chain() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 5);
+    let tip_info = contract.infos.get(&hash_of(&headers[4])).unwrap();
+
+    assert_eq!(
+        contract.recompute_total_difficulty(10_234_002, 5),
+        Some(tip_info.total_difficulty)
+    );
+    assert_eq!(
+        contract.recompute_total_difficulty(10_234_004, 3),
+        Some(tip_info.total_difficulty)
+    );
+    assert_eq!(contract.recompute_total_difficulty(10_234_002, 6), None);
+    assert_eq!(
+        contract.recompute_total_difficulty(10_234_002, u64::max_value()),
+        None
+    );
+}
+
+#[test]
+fn recompute_total_difficulty_overflow_is_none() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let first_hash = hash_of(&first);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 2);
+
+    let mut info = contract.infos.get(&first_hash).unwrap();
+    info.total_difficulty = U256(!U256::default().0);
+    contract.infos.insert(&first_hash, &info);
+
+    assert_eq!(contract.recompute_total_difficulty(10_234_002, 1), None);
+}
+
+#[test]
+#[should_panic(expected = "Parent header should be present")]
+fn header_from_unrelated_chain_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    // A valid ethash header, which does not descend from the first header.
+    let block = read_block("./src/data/8996777.json".to_string());
+    contract.add_block_header(
+        block.header_rlp.0.clone(),
+        block.to_double_node_with_merkle_proof_vec(),
+    );
+}
+
+#[test]
+fn gc_preview_matches_prune_below() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 5);
+    contract.add_block_header(fake_child(&headers[0], 5, 1), vec![]);
+
+    let preview = contract.gc_preview(10_234_004);
+    assert_eq!(preview, 4);
+    let headers_count = contract.headers.len();
+    contract.prune_below(10_234_004);
+    assert_eq!(contract.headers.len(), headers_count - preview);
+    assert_eq!(contract.gc_preview(10_234_004), 0);
+}
+
+#[test]
+fn gc_preview_is_capped_like_prune_below() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 5);
+    contract.add_block_header(fake_child(&headers[0], 5, 1), vec![]);
+    contract.set_max_gc_per_call(2);
+
+    // Only the numbers 10_234_001 and 10_234_002 fit into one call.
+    assert_eq!(contract.gc_preview(10_234_004), 2);
+    let headers_count = contract.headers.len();
+    contract.prune_below(10_234_004);
+    assert_eq!(contract.headers.len(), headers_count - 2);
+    // The fork doubles the number 10_234_003, the last one below 10_234_004.
+    assert_eq!(contract.gc_preview(10_234_004), 2);
+    contract.prune_below(10_234_004);
+    assert_eq!(contract.gc_preview(10_234_004), 0);
+}
+
+#[test]
+fn header_roots_of_known_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let contract = trusted_contract(first.clone());
+    let header: BlockHeader = rlp::decode(&first).unwrap();
+
+    assert_eq!(
+        contract.header_roots(hash_of(&first)),
+        Some((
+            header.state_root,
+            header.transactions_root,
+            header.receipts_root
+        ))
+    );
+    assert_eq!(contract.header_roots(H256::default()), None);
+}
+
+#[test]
+fn run_gc_finishes_bounded_gc() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_max_gc_per_call(1);
+    let headers = add_fake_chain(&mut contract, &first, 10);
+
+    // A heavier fork moves the best header four numbers ahead at once.
+    let mut fork = headers[4].clone();
+    for difficulty in vec![5, 5, 5, 5, 5, 5, 5, 5, 15] {
+        fork = fake_child(&fork, difficulty, 1);
+        contract.add_block_header(fork.clone(), vec![]);
+    }
+    assert_eq!(contract.last_block_number(), 10_234_015);
+    assert_eq!(contract.number_of(hash_of(&headers[0])), None);
+    assert_eq!(contract.number_of(hash_of(&headers[1])), Some(10_234_003));
+
+    for _ in 0..3 {
+        contract.run_gc();
+    }
+    assert_eq!(contract.number_of(hash_of(&headers[3])), None);
+    assert_eq!(contract.number_of(hash_of(&headers[4])), Some(10_234_006));
+
+    contract.run_gc();
+    assert_eq!(contract.number_of(hash_of(&headers[4])), Some(10_234_006));
+}
+
+#[test]
+fn canonical_parent_ok_detects_corruption() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 3);
+    assert!(contract.canonical_parent_ok(10_234_002));
+    assert!(contract.canonical_parent_ok(10_234_004));
+    assert!(!contract.canonical_parent_ok(10_234_001));
+    assert!(!contract.canonical_parent_ok(10_234_005));
+
+    contract
+        .canonical_header_hashes
+        .insert(&10_234_002, &H256::default());
+    assert!(!contract.canonical_parent_ok(10_234_002));
+    assert!(!contract.canonical_parent_ok(10_234_003));
+    assert!(contract.canonical_parent_ok(10_234_004));
+}
+
+#[test]
+fn tie_break_by_higher_number() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let a1 = fake_child(&first, 21, 0);
+    let c1 = fake_child(&first, 21, 2);
+    let b1 = fake_child(&first, 10, 1);
+    let b2 = fake_child(&b1, 11, 1);
+
+    let mut by_parity = trusted_contract(first.clone());
+    let mut by_number = trusted_contract(first.clone());
+    by_number.set_tie_break(TieBreak::HigherNumber);
+    for contract in vec![&mut by_parity, &mut by_number] {
+        for header in vec![&a1, &c1, &b1, &b2] {
+            contract.add_block_header(header.clone(), vec![]);
+            if header == &c1 {
+                // Equal numbers keep the header seen first.
+                assert_eq!(contract.block_hash(10_234_002), Some(hash_of(&a1)));
+            }
+        }
+    }
+
+    assert_eq!(by_parity.last_block_number(), 10_234_002);
+    assert_eq!(by_parity.block_hash(10_234_002), Some(hash_of(&a1)));
+    assert_eq!(by_number.last_block_number(), 10_234_003);
+    assert_eq!(by_number.block_hash(10_234_002), Some(hash_of(&b1)));
+}
+
+#[test]
+fn dag_merkle_roots_range() {
+    testing_env!(get_context(vec![], false));
+    let roots = read_roots_collection().dag_merkle_roots;
+    let contract = EthClient::init(
+        true,
+        10,
+        roots.clone(),
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    assert_eq!(contract.dag_merkle_roots(10, 5), roots[..5].to_vec());
+    assert_eq!(contract.dag_merkle_roots(20, 3), roots[10..13].to_vec());
+    assert_eq!(contract.dag_merkle_roots(519, 10), roots[509..].to_vec());
+    assert!(contract.dag_merkle_roots(5, 3).is_empty());
+}
+
+#[test]
+fn trusted_header_is_not_pow_verified() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 1);
+    assert_eq!(contract.is_pow_verified(hash_of(&headers[0])), Some(false));
+}
+
+#[test]
+fn recommended_gas_grows_with_epoch() {
+    testing_env!(get_context(vec![], false));
+    let early = trusted_contract(read_header(2));
+    let late = trusted_contract(read_header(10_234_001));
+    assert!(early.recommended_gas() < late.recommended_gas());
+}
+
+#[test]
+fn stale_tip_is_stalled() {
+    let first = read_header(10_234_001);
+    let tip: BlockHeader = rlp::decode(first.as_slice()).unwrap();
+    let mut context = get_context(vec![], false);
+    context.block_timestamp = (tip.timestamp + 1000) * 1_000_000_000;
+    testing_env!(context);
+    let contract = trusted_contract(first);
+    assert!(!contract.is_stalled(2000));
+    assert!(contract.is_stalled(60));
+}
+
+/// Builds a child of `parent` with `extra_data_len` bytes of extra data. Its PoW is only
+/// acceptable by a bridge which does not validate ethash, thanks to the difficulty of 1.
+fn child_with_extra_data(parent: &[u8], extra_data_len: usize) -> Vec<u8> {
+    let mut header: BlockHeader = rlp::decode(&fake_child(parent, 1, 0)).unwrap();
+    header.extra_data = vec![0; extra_data_len];
+    rlp::encode(&header)
+}
+
+#[test]
+#[should_panic(expected = "should be valid")]
+fn oversized_extra_data_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        false,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    contract.add_block_header(
+        child_with_extra_data(&first, 33),
+        block.to_double_node_with_merkle_proof_vec(),
+    );
+}
+
+#[test]
+fn extra_data_limit_is_configurable() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        false,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    contract.set_max_extra_data_bytes(33);
+    let block = read_block("./src/data/10234002.json".to_string());
+    let header = child_with_extra_data(&first, 33);
+    contract.add_block_header(header.clone(), block.to_double_node_with_merkle_proof_vec());
+    assert_eq!(contract.block_hash(10_234_002), Some(hash_of(&header)));
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 3);
+    let tip = hash_of(&headers[2]);
+
+    let mut restored =
+        EthClient::init_with_checkpoint(contract.export_checkpoint(), b"restored".to_vec());
+    assert_eq!(restored.last_block_number(), 10_234_004);
+    assert_eq!(restored.block_hash(10_234_004), Some(tip));
+    assert!(restored.confirm_total_difficulty(tip, U256(30.into())));
+    assert_eq!(restored.dag_config(), contract.dag_config());
+
+    let next = add_fake_chain(&mut restored, &headers[2], 1);
+    assert_eq!(restored.block_hash(10_234_005), Some(hash_of(&next[0])));
+}
+
+#[test]
+fn matches_canonical_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let canonical = fake_child(&first, 21, 0);
+    let sibling = fake_child(&first, 11, 1);
+    contract.add_block_header(canonical.clone(), vec![]);
+    contract.add_block_header(sibling.clone(), vec![]);
+
+    assert!(contract.matches_canonical(first));
+    assert!(contract.matches_canonical(canonical.clone()));
+    assert!(!contract.matches_canonical(sibling));
+    assert!(!contract.matches_canonical(fake_child(&canonical, 21, 0)));
+}
+
+#[test]
+fn matches_canonical_rejects_malformed_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let contract = trusted_contract(first.clone());
+
+    assert!(!contract.matches_canonical(first[..first.len() - 1].to_vec()));
+    assert!(!contract.matches_canonical(vec![]));
+}
+
+#[test]
+fn fork_counts_per_number() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let a1 = fake_child(&first, 21, 0);
+    contract.add_block_header(a1.clone(), vec![]);
+    contract.add_block_header(fake_child(&first, 11, 1), vec![]);
+    for salt in 0..3 {
+        contract.add_block_header(fake_child(&a1, 11, salt), vec![]);
+    }
+
+    assert_eq!(contract.fork_counts(10_234_000, 5), vec![0, 1, 2, 3, 0]);
+    assert_eq!(contract.fork_counts(10_234_002, 1), vec![2]);
+    assert!(contract.fork_counts(10_234_001, 0).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Epoch is before the first DAG merkle root")]
+fn dag_merkle_root_below_start_epoch() {
+    testing_env!(get_context(vec![], false));
+    let contract = EthClient::init(
+        true,
+        10,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    contract.dag_merkle_root(9);
+}
+
+#[test]
+fn finalized_at_timestamp_finds_block() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let timestamp = rlp::decode::<BlockHeader>(&first).unwrap().timestamp;
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        3,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    // Headers 10_234_002..=10_234_009 with timestamps increasing by one.
+    let headers = add_fake_chain(&mut contract, &first, 8);
+
+    assert_eq!(
+        contract.finalized_at_timestamp(timestamp + 3),
+        Some(hash_of(&headers[2]))
+    );
+    // Blocks above the safe block 10_234_006 are not finalized yet.
+    assert_eq!(
+        contract.finalized_at_timestamp(timestamp + 100),
+        Some(hash_of(&headers[4]))
+    );
+    assert_eq!(
+        contract.finalized_at_timestamp(timestamp),
+        Some(hash_of(&first))
+    );
+    assert_eq!(contract.finalized_at_timestamp(timestamp - 1), None);
+}
+
+#[test]
+fn too_deep_reorg_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        3,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    let headers = add_fake_chain(&mut contract, &first, 6);
+
+    let heavy_fork = fake_child(&first, 1001, 1);
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(heavy_fork, vec![])
+    }));
+    assert!(result.is_err());
+    assert_eq!(contract.last_block_number(), 10_234_007);
+    for (i, header) in headers.iter().enumerate() {
+        assert_eq!(
+            contract.block_hash(10_234_002 + i as u64),
+            Some(hash_of(header))
+        );
+    }
+
+    // A branch forking below the final blocks is rejected even once its tip is above them.
+    let mut branch_tip = headers[0].clone();
+    for _ in 0..3 {
+        branch_tip = fake_child(&branch_tip, 1, 2);
+        contract.add_block_header(branch_tip.clone(), vec![]);
+    }
+    let deep_fork = fake_child(&branch_tip, 1001, 2);
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(deep_fork, vec![])
+    }));
+    assert!(result.is_err());
+    assert_eq!(contract.block_hash(10_234_003), Some(hash_of(&headers[1])));
+
+    // A reorg within the confirmations is still accepted.
+    let shallow_fork = fake_child(&headers[2], 1001, 1);
+    contract.add_block_header(shallow_fork.clone(), vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_005);
+    assert_eq!(
+        contract.block_hash(10_234_005),
+        Some(hash_of(&shallow_fork))
+    );
+}
+
+#[test]
+fn next_gc_target_is_frontier() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    assert_eq!(
+        contract.next_gc_target(),
+        Some((10_234_001, vec![hash_of(&first)]))
+    );
+
+    let headers = add_fake_chain(&mut contract, &first, 15);
+    assert_eq!(
+        contract.next_gc_target(),
+        Some((10_234_007, vec![hash_of(&headers[5])]))
+    );
+
+    let fork = fake_child(&headers[4], 5, 1);
+    contract.add_block_header(fork.clone(), vec![]);
+    assert_eq!(
+        contract.next_gc_target(),
+        Some((10_234_007, vec![hash_of(&headers[5]), hash_of(&fork)]))
+    );
+}
+
+#[test]
+fn gc_frontier_survives_gaps() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 8);
+
+    // Numbers without headers above the lowest one, as left by `prune_fork`.
+    for number in 10_234_002..=10_234_005 {
+        contract.all_header_hashes.remove(&number);
+    }
+    contract.canonical_header_hashes.remove(&10_234_003);
+    assert_eq!(
+        contract.next_gc_target(),
+        Some((10_234_001, vec![hash_of(&first)]))
+    );
+    assert_eq!(contract.earliest_available_block(), 10_234_001);
+
+    // Once the lowest number is removed, the next target is found past the gap.
+    add_fake_chain(&mut contract, &headers[7], 3);
+    assert_eq!(
+        contract.next_gc_target(),
+        Some((10_234_006, vec![hash_of(&headers[4])]))
+    );
+}
+
+#[test]
+#[should_panic(expected = "is not the expected one")]
+fn add_block_header_checked_rejects_wrong_hash() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let header = fake_child(&first, 10, 0);
+    contract.add_block_header_checked(header.clone(), hash_of(&header), vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_002);
+    contract.add_block_header_checked(fake_child(&header, 10, 0), hash_of(&header), vec![]);
+}
+
+#[test]
+fn dag_roots_commitment_matches_roots() {
+    testing_env!(get_context(vec![], false));
+    let roots = read_roots_collection().dag_merkle_roots;
+    let contract = trusted_contract(read_header(10_234_001));
+    let mut data = vec![];
+    for root in &roots {
+        data.extend_from_slice(&(root.0).0);
+    }
+    assert_eq!(
+        contract.dag_roots_commitment(),
+        H256::from(near_keccak256(&data))
+    );
+}
+
+#[test]
+fn verify_and_extract_verified_header() {
+    testing_env!(get_context(vec![], false));
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let header: BlockHeader = rlp::decode(&block.header_rlp.0).unwrap();
+    assert_eq!(
+        contract.verify_and_extract(
+            block.header_rlp.0.clone(),
+            block.to_double_node_with_merkle_proof_vec()
+        ),
+        Some((
+            10_234_002,
+            header.hash.unwrap(),
+            header.state_root,
+            header.receipts_root
+        ))
+    );
+    assert_eq!(contract.last_block_number(), 10_234_001);
+
+    let orphan = read_block("./src/data/10234003.json".to_string());
+    assert_eq!(
+        contract.verify_and_extract(
+            orphan.header_rlp.0.clone(),
+            orphan.to_double_node_with_merkle_proof_vec()
+        ),
+        None
+    );
+}
+
+#[test]
+fn verify_and_extract_rejects_malformed_input() {
+    testing_env!(get_context(vec![], false));
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let header = block.header_rlp.0.clone();
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+
+    let mut swapped = dag_nodes.clone();
+    swapped.swap(0, 1);
+    let mut corrupted = dag_nodes.clone();
+    corrupted[3].proof.pop();
+    let mut misshapen = dag_nodes.clone();
+    misshapen[2].dag_nodes.pop();
+    for bad_nodes in vec![
+        swapped,
+        corrupted,
+        misshapen,
+        dag_nodes[..10].to_vec(),
+        vec![],
+    ] {
+        assert_eq!(contract.verify_and_extract(header.clone(), bad_nodes), None);
+    }
+    assert_eq!(
+        contract.verify_and_extract(header[..header.len() - 1].to_vec(), dag_nodes),
+        None
+    );
+}
+
+#[test]
+fn reverify_stored_header() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+    contract.add_block_header(block.header_rlp.0.clone(), dag_nodes.clone());
+    let hash = hash_of(&block.header_rlp.0);
+    assert!(contract.reverify(hash, dag_nodes.clone()));
+    assert!(!contract.reverify(hash_of(&read_header(10_234_001)), dag_nodes.clone()));
+
+    let mut swapped = dag_nodes.clone();
+    swapped.swap(0, 1);
+    assert!(!contract.reverify(hash, swapped));
+    assert!(!contract.reverify(hash, dag_nodes[..10].to_vec()));
+    assert!(!contract.reverify(hash, vec![]));
+
+    let mut corrupted = contract.headers.get(&hash).unwrap();
+    corrupted.timestamp = 0;
+    contract.headers.insert(&hash, &corrupted);
+    assert!(!contract.reverify(hash, dag_nodes));
+}
+
+#[test]
+#[should_panic(expected = "is too far ahead of the best header")]
+fn header_beyond_gap_is_rejected() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_max_header_gap(2);
+    contract.add_block_header(fake_child(&first, 101, 0), vec![]);
+    // A lighter fork grows past the best header without becoming canonical.
+    let mut fork = first;
+    for _ in 0..3 {
+        fork = fake_child(&fork, 1, 1);
+        contract.add_block_header(fork.clone(), vec![]);
+    }
+    assert_eq!(contract.last_block_number(), 10_234_002);
+    contract.add_block_header(fake_child(&fork, 1, 1), vec![]);
+}
+
+#[test]
+fn header_status_of_headers() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let canonical = fake_child(&first, 21, 0);
+    let sibling = fake_child(&first, 11, 1);
+    contract.add_block_header(canonical.clone(), vec![]);
+    contract.add_block_header(sibling.clone(), vec![]);
+
+    assert_eq!(contract.header_status(hash_of(&canonical)), 2);
+    assert_eq!(contract.header_status(hash_of(&sibling)), 1);
+    assert_eq!(contract.header_status(H256::default()), 0);
+}
+
+fn clique_address(secret_key: &secp256k1::SecretKey) -> Address {
+    let public_key = secp256k1::PublicKey::from_secret_key(secret_key);
+    Address::from(&near_keccak256(&public_key.serialize()[1..])[12..])
+}
+
+/// Builds a child of `parent` sealed by the clique signer with the given secret key.
+fn clique_child(parent: &[u8], secret_key: &secp256k1::SecretKey) -> Vec<u8> {
+    let mut header: BlockHeader = rlp::decode(&fake_child(parent, 2, 0)).unwrap();
+    header.extra_data = vec![0; 32];
+    let message = secp256k1::Message::parse(&near_keccak256(&rlp::encode(&header)));
+    let (signature, recovery_id) = secp256k1::sign(&message, secret_key);
+    header.extra_data.extend_from_slice(&signature.serialize());
+    header.extra_data.push(recovery_id.serialize());
+    rlp::encode(&header)
+}
+
+#[test]
+fn clique_header_from_signer_is_accepted() {
+    testing_env!(get_self_context());
+    let signer = secp256k1::SecretKey::parse(&[1; 32]).unwrap();
+    let stranger = secp256k1::SecretKey::parse(&[2; 32]).unwrap();
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    contract.set_consensus(ConsensusMode::Clique {
+        signers: vec![clique_address(&signer)],
+    });
+    contract.set_max_extra_data_bytes(97);
+
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(clique_child(&first, &stranger), vec![])
+    }));
+    assert!(result.is_err());
+
+    let header = clique_child(&first, &signer);
+    contract.add_block_header(header.clone(), vec![]);
+    assert_eq!(contract.block_hash(10_234_002), Some(hash_of(&header)));
+    assert_eq!(contract.is_pow_verified(hash_of(&header)), Some(true));
+}
+
+#[test]
+fn sha256_merkle_leaf_matches_apply_merkle_proof() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234002.json".to_string());
+    let node = DoubleNodeWithMerkleProof {
+        dag_nodes: block.to_double_node_with_merkle_proof_vec()[0]
+            .dag_nodes
+            .clone(),
+        proof: vec![],
+    };
+    let mut pair = vec![];
+    pair.extend_from_slice(&(node.dag_nodes[0].0).0);
+    pair.extend_from_slice(&(node.dag_nodes[1].0).0);
+    assert_eq!(
+        DoubleNodeWithMerkleProof::sha256_merkle_leaf(&pair),
+        node.apply_merkle_proof(0)
+    );
+}
+
+#[test]
+fn blocks_behind_remote_tip() {
+    testing_env!(get_context(vec![], false));
+    let contract = trusted_contract(read_header(10_234_001));
+    assert_eq!(contract.blocks_behind(10_234_011), 10);
+    assert_eq!(contract.blocks_behind(10_234_001), 0);
+    assert_eq!(contract.blocks_behind(10_000_000), 0);
+}
+
+#[test]
+fn repeated_dag_node_is_verified_once() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234002.json".to_string());
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+    let indices = contract.dag_access_indices(block.header_rlp.0.clone(), dag_nodes.clone());
+
+    let mut verified = VerifiedNodes::new(contract.dag_merkle_root(10_234_002 / 30000));
+    assert!(verified.verify(0, indices[0] / 2, &dag_nodes[0]).is_ok());
+    assert!(verified.verify(0, indices[0] / 2, &dag_nodes[0]).is_ok());
+    assert!(verified.verify(1, indices[2] / 2, &dag_nodes[1]).is_ok());
+    assert_eq!(verified.proofs_applied, 2);
+    assert!(verified.verify(1, indices[0] / 2, &dag_nodes[1]).is_err());
+
+    contract.add_block_header(block.header_rlp.0.clone(), dag_nodes);
+    assert_eq!(contract.last_block_number(), 10_234_002);
+}
+
+#[test]
+fn required_dag_nodes_matches_ethash() {
+    testing_env!(get_context(vec![], false));
+    let contract = trusted_contract(read_header(10_234_001));
+    let block = read_block("./src/data/10234002.json".to_string());
+    assert_eq!(contract.required_dag_nodes(), 64);
+    assert_eq!(
+        contract.required_dag_nodes() as usize,
+        block.to_double_node_with_merkle_proof_vec().len()
+    );
+}
+
+#[test]
+fn trusted_extension_without_difficulty_wins_tie() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 1);
+    let child = fake_child(&headers[0], 0, 0);
+    contract.add_block_header(child.clone(), vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_003);
+    assert_eq!(contract.block_hash(10_234_003), Some(hash_of(&child)));
+}
+
+#[test]
+fn header_logs_bloom_of_known_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let header: BlockHeader = rlp::decode(&first).unwrap();
+    assert_eq!(
+        contract.header_logs_bloom(hash_of(&first)),
+        Some((header.log_bloom.0).0.to_vec())
+    );
+
+    add_fake_chain(&mut contract, &first, 11);
+    assert_eq!(contract.header_logs_bloom(hash_of(&first)), None);
+}
+
+#[test]
+fn orphans_are_added_with_their_parent() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_on_missing_parent(OnMissingParent::Buffer);
+    let a = fake_child(&first, 10, 0);
+    let b = fake_child(&a, 10, 0);
+    let c = fake_child(&b, 10, 0);
+
+    contract.add_block_header(c.clone(), vec![]);
+    contract.add_block_header(b.clone(), vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_001);
+    assert_eq!(contract.number_of(hash_of(&b)), None);
+
+    contract.add_block_header(a.clone(), vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_004);
+    assert_eq!(contract.block_hash(10_234_003), Some(hash_of(&b)));
+    assert_eq!(contract.block_hash(10_234_004), Some(hash_of(&c)));
+    assert_eq!(contract.orphans.len(), 0);
+}
+
+#[test]
+fn header_seal_of_known_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let contract = trusted_contract(first.clone());
+    let header: BlockHeader = rlp::decode(&first).unwrap();
+    assert_eq!(
+        contract.header_seal(hash_of(&first)),
+        Some((header.nonce, header.mix_hash))
+    );
+    assert_eq!(contract.header_seal(H256::default()), None);
+}
+
+#[test]
+fn epoch_submission_counts() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(2)).unwrap();
+    first.number = 29_998;
+    let first = rlp::encode(&first);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 3);
+
+    assert_eq!(contract.epoch_submission_count(0), 1);
+    assert_eq!(contract.epoch_submission_count(1), 2);
+    assert_eq!(contract.epoch_submission_count(2), 0);
+}
+
+#[test]
+fn replace_dag_roots_requires_pause() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let roots = read_roots_collection().dag_merkle_roots;
+    let new_roots = roots[300..].to_vec();
+
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.replace_dag_roots(300, new_roots.clone(), "fix".to_string())
+    }));
+    assert!(result.is_err());
+
+    contract.pause();
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(fake_child(&first, 10, 0), vec![])
+    }));
+    assert!(result.is_err());
+    contract.replace_dag_roots(300, new_roots.clone(), "fix".to_string());
+    contract.unpause();
+
+    assert_eq!(contract.dag_config(), (300, 30000));
+    assert_eq!(contract.dag_merkle_root(341), roots[341]);
+    let mut data = vec![];
+    for root in &new_roots {
+        data.extend_from_slice(&(root.0).0);
+    }
+    assert_eq!(
+        contract.dag_roots_commitment(),
+        H256::from(near_keccak256(&data))
+    );
+    add_fake_chain(&mut contract, &first, 1);
+}
+
+#[test]
+fn migrate_bumps_state_version() {
+    testing_env!(get_self_context());
+    let contract = trusted_contract(read_header(10_234_001));
+    assert_eq!(contract.state_version(), 1);
+    near_sdk::env::state_write(&contract);
+
+    let migrated = EthClient::migrate();
+    assert_eq!(migrated.state_version(), 2);
+    assert_eq!(migrated.last_block_number(), 10_234_001);
+}
+
+#[test]
+fn extends_tip_batches() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 1);
+    let a = fake_child(&headers[0], 10, 0);
+    let b = fake_child(&a, 10, 0);
+
+    assert!(contract.extends_tip(vec![a.clone(), b.clone()]));
+    assert!(!contract.extends_tip(vec![b.clone()]));
+    assert!(!contract.extends_tip(vec![b, a]));
+    assert!(!contract.extends_tip(vec![fake_child(&first, 10, 1)]));
+    assert!(!contract.extends_tip(vec![]));
+}
+
+#[test]
+fn extends_tip_rejects_malformed_headers() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let contract = trusted_contract(first.clone());
+    let a = fake_child(&first, 10, 0);
+    let b = fake_child(&a, 10, 0);
+
+    assert!(!contract.extends_tip(vec![a.clone(), b[..b.len() / 2].to_vec()]));
+    assert!(!contract.extends_tip(vec![vec![0xff, 0x00], b]));
+}
+
+#[test]
+fn expected_difficulty_matches_mainnet_across_bomb_delays() {
+    testing_env!(get_context(vec![], false));
+    // Frontier, Constantinople (bomb delayed by 5M blocks) and Muir Glacier (delayed by 9M).
+    for &(parent, child) in &[
+        (2, 3),
+        (400_000, 400_001),
+        (8_996_776, 8_996_777),
+        (10_234_001, 10_234_002),
+        (10_234_010, 10_234_011),
+    ] {
+        let parent: BlockHeader = rlp::decode(&read_header(parent)).unwrap();
+        let child: BlockHeader = rlp::decode(&read_header(child)).unwrap();
+        assert_eq!(
+            EthClient::expected_difficulty(&child, &parent),
+            child.difficulty
+        );
+    }
+}
+
+#[test]
+fn expected_difficulty_at_fork_boundaries() {
+    testing_env!(get_context(vec![], false));
+    // No mainnet headers around the forks are bundled, so the expected values follow the EIPs for
+    // a parent with uncles mined 15 seconds before its child.
+    let mut parent: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    parent.difficulty = U256(3_000_000_000_000_000u64.into());
+    parent.uncles_hash = H256::default();
+    let mut child = parent.clone();
+    child.timestamp = parent.timestamp + 15;
+    for &(number, expected) in &[
+        // Homestead, EIP-2.
+        (1_149_999, 2_998_535_156_250_512u64),
+        (1_150_000, 3_000_000_000_000_512),
+        // Byzantium, EIP-100 and EIP-649.
+        (4_369_999, 3_002_199_023_255_552),
+        (4_370_000, 3_001_464_843_752_048),
+        // Constantinople, EIP-1234.
+        (7_279_999, 3_002_564_355_377_776),
+        (7_280_000, 3_001_464_844_798_576),
+        // Muir Glacier, EIP-2384.
+        (9_199_999, 3_002_014_599_563_888),
+        (9_200_000, 3_001_464_843_750_001),
+        // London, EIP-3554.
+        (12_964_999, 3_001_602_282_703_472),
+        (12_965_000, 3_001_465_917_491_824),
+        // Arrow Glacier, EIP-4345.
+        (13_772_999, 3_001_739_721_656_944),
+        (13_773_000, 3_001_465_112_185_456),
+        // Gray Glacier, EIP-5133.
+        (15_049_999, 3_003_663_867_005_552),
+        (15_050_000, 3_001_482_023_619_184),
+    ] {
+        parent.number = number - 1;
+        child.number = number;
+        assert_eq!(
+            EthClient::expected_difficulty(&child, &parent),
+            U256(expected.into()),
+            "block {}",
+            number
+        );
+    }
+}
+
+#[test]
+fn wrong_difficulty_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let parent: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    let mut child: BlockHeader = rlp::decode(&read_header(10_234_002)).unwrap();
+    assert!(contract.verify_fields(&child, &parent).is_ok());
+
+    child.difficulty = child.difficulty * 102 / 100;
+    assert!(contract.verify_fields(&child, &parent).is_err());
+}
+
+#[test]
+fn canonical_at_or_below_clamps_to_tip() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 3);
+
+    let tip = (10_234_004, hash_of(&headers[2]));
+    assert_eq!(contract.canonical_at_or_below(20_000_000), Some(tip));
+    assert_eq!(contract.canonical_at_or_below(10_234_004), Some(tip));
+    assert_eq!(
+        contract.canonical_at_or_below(10_234_002),
+        Some((10_234_002, hash_of(&headers[0])))
+    );
+    assert_eq!(contract.canonical_at_or_below(10_234_000), None);
+}
+
+#[test]
+fn is_finalized_block_requires_confirmations_and_canonical_hash() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let fork = fake_child(&first, 9, 1);
+    contract.add_block_header(fork.clone(), vec![]);
+    let headers = add_fake_chain(&mut contract, &first, 12);
+
+    // The tip is 10_234_013, so with 10 confirmations the safe block is 10_234_003.
+    assert!(contract.is_finalized_block(10_234_002, hash_of(&headers[0])));
+    assert!(!contract.is_finalized_block(10_234_012, hash_of(&headers[10])));
+    assert!(!contract.is_finalized_block(10_234_002, hash_of(&fork)));
+}
+
+#[test]
+fn pow_verifications_count_verified_headers() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    assert_eq!(contract.pow_verifications(), 0);
+
+    for (i, number) in (10_234_002..10_234_004).enumerate() {
+        let block = read_block(format!("./src/data/{}.json", number));
+        contract.add_block_header(
+            block.header_rlp.0.clone(),
+            block.to_double_node_with_merkle_proof_vec(),
+        );
+        assert_eq!(contract.pow_verifications(), i as u64 + 1);
+    }
+}
+
+#[test]
+fn unvalidated_headers_are_not_counted_as_pow_verifications() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        false,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    for number in 10_234_002..10_234_004 {
+        let block = read_block(format!("./src/data/{}.json", number));
+        contract.add_block_header(
+            block.header_rlp.0.clone(),
+            block.to_double_node_with_merkle_proof_vec(),
+        );
+    }
+    assert_eq!(contract.last_block_number(), 10_234_003);
+    assert_eq!(contract.pow_verifications(), 0);
+}
+
+#[test]
+fn trusted_headers_are_not_counted_as_pow_verifications() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 2);
+    assert_eq!(contract.pow_verifications(), 0);
+}
+
+#[test]
+fn gc_impact_of_counts_numbers_past_the_gc_boundary() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 4);
+    assert_eq!(contract.gc_impact_of(fake_child(&headers[3], 11, 0)), 0);
+
+    // With 10_234_011 as the best header the header 10_234_001 was removed, 10_234_012 moves
+    // the boundary of `finalized_gc_threshold` by one more number.
+    let headers = add_fake_chain(&mut contract, &headers[3], 6);
+    let next = fake_child(&headers[5], 11, 0);
+    assert_eq!(contract.gc_impact_of(next.clone()), 1);
+    assert_eq!(contract.next_gc_target().unwrap().0, 10_234_002);
+
+    contract.add_block_header(next, vec![]);
+    assert_eq!(contract.next_gc_target().unwrap().0, 10_234_003);
+}
+
+#[test]
+fn gc_impact_of_malformed_header_is_zero() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 10);
+    let next = fake_child(&headers[9], 11, 0);
+    assert_eq!(contract.gc_impact_of(next.clone()), 1);
+
+    assert_eq!(contract.gc_impact_of(next[1..].to_vec()), 0);
+}
+
+#[test]
+#[should_panic(expected = "No DAG merkle roots are configured")]
+fn empty_dag_merkle_roots_fail_verification_cleanly() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        vec![],
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    contract.add_block_header(
+        block.header_rlp.0.clone(),
+        block.to_double_node_with_merkle_proof_vec(),
+    );
+}
+
+#[test]
+fn recent_canonical_starts_at_tip_and_stops_at_gc() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 35);
+
+    let recent = contract.recent_canonical(3);
+    assert_eq!(
+        recent,
+        vec![
+            hash_of(&headers[34]),
+            hash_of(&headers[33]),
+            hash_of(&headers[32])
+        ]
+    );
+
+    // With `hashes_gc_threshold` of 30 only 10_234_007..=10_234_036 are left.
+    let recent = contract.recent_canonical(50);
+    assert_eq!(recent.len(), 30);
+    assert_eq!(*recent.last().unwrap(), hash_of(&headers[5]));
+}
+
+#[test]
+fn zero_gas_limit_parent_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let contract = trusted_contract(read_header(10_234_001));
+    let mut parent: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    parent.gas_limit = U256::default();
+    let child: BlockHeader = rlp::decode(&read_header(10_234_002)).unwrap();
+    assert_eq!(
+        contract.verify_fields(&child, &parent),
+        Err("Header 10234001 has zero gas limit".to_string())
+    );
+}
+
+#[test]
+fn storage_prefix_is_the_init_prefix() {
+    testing_env!(get_context(vec![], false));
+    let contract = EthClient::init(
+        true,
+        0,
+        vec![],
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        Some("bob.near".to_string()),
+        b"mainnet".to_vec(),
+    );
+    assert_eq!(contract.storage_prefix(), b"mainnet".to_vec());
+}
+
+#[test]
+fn tighter_difficulty_tolerance_rejects_deviation() {
+    testing_env!(get_self_context());
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let parent: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    let mut child: BlockHeader = rlp::decode(&read_header(10_234_002)).unwrap();
+    child.difficulty = child.difficulty * 1005 / 1000;
+    assert!(contract.verify_fields(&child, &parent).is_ok());
+
+    contract.set_difficulty_tolerance_permille(1);
+    assert!(contract.verify_fields(&child, &parent).is_err());
+
+    // The whole expected difficulty is the widest tolerance.
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.set_difficulty_tolerance_permille(1001)
+    }));
+    assert!(result.is_err());
+    contract.set_difficulty_tolerance_permille(1000);
+    child.difficulty = U256(1.into());
+    assert!(contract.verify_fields(&child, &parent).is_ok());
+}
+
+#[test]
+fn last_was_reorg_tracks_the_last_submission() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 2);
+    assert!(!contract.last_was_reorg());
+
+    let fork = fake_child(&first, 25, 1);
+    contract.add_block_header(fork.clone(), vec![]);
+    assert!(contract.last_was_reorg());
+
+    add_fake_chain(&mut contract, &fork, 1);
+    assert!(!contract.last_was_reorg());
+}
+
+#[test]
+fn epochs_remaining_decrease_with_new_epochs() {
+    testing_env!(get_context(vec![], false));
+    // The roots cover the epochs 0..512, the header 10_259_999 is the last one of the epoch 341.
+    let mut last_of_epoch: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    last_of_epoch.number = 10_259_999;
+    let last_of_epoch = rlp::encode(&last_of_epoch);
+    let mut contract = trusted_contract(last_of_epoch.clone());
+    assert_eq!(contract.epochs_remaining(), 170);
+    add_fake_chain(&mut contract, &last_of_epoch, 1);
+    assert_eq!(contract.epochs_remaining(), 169);
+}
+
+#[test]
+fn compact_dag_nodes_round_trip() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+    let packed = DoubleNodeWithMerkleProof::pack(&dag_nodes);
+    assert!(packed.len() < dag_nodes.try_to_vec().unwrap().len());
+    assert_eq!(
+        DoubleNodeWithMerkleProof::unpack(&packed)
+            .try_to_vec()
+            .unwrap(),
+        dag_nodes.try_to_vec().unwrap()
+    );
+
+    contract.add_block_header_compact(block.header_rlp.0.clone(), packed);
+    assert_eq!(contract.last_block_number(), 10_234_002);
+}
+
+#[test]
+fn canonical_header_returns_rlp() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 2);
+
+    let header: BlockHeader = rlp::decode(&contract.canonical_header(10_234_003).unwrap()).unwrap();
+    assert_eq!(header.number, 10_234_003);
+    assert_eq!(header.hash.unwrap(), hash_of(&headers[1]));
+    assert_eq!(contract.canonical_header(10_234_004), None);
+}
+
+#[test]
+#[should_panic(expected = "dag proof epoch mismatch at node 5")]
+fn proof_from_another_epoch_is_reported() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let mut dag_nodes = block.to_double_node_with_merkle_proof_vec();
+    // The header 8_996_777 is in the epoch 299 rather than 341.
+    dag_nodes[5] = read_block("./src/data/8996777.json".to_string())
+        .to_double_node_with_merkle_proof_vec()
+        .remove(5);
+    contract.add_block_header(block.header_rlp.0.clone(), dag_nodes);
+}
+
+#[test]
+fn trust_root_is_the_first_header_or_the_checkpoint() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 3);
+    assert_eq!(contract.trust_root(), hash_of(&first));
+
+    let restored =
+        EthClient::init_with_checkpoint(contract.export_checkpoint(), b"restored".to_vec());
+    assert_eq!(restored.trust_root(), hash_of(&headers[2]));
+}
+
+#[test]
+fn get_headers_skips_pruned_and_unknown_hashes() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 11);
+
+    // With `finalized_gc_threshold` of 10 the first header was garbage collected.
+    let found = contract.get_headers(vec![
+        hash_of(&first),
+        hash_of(&headers[10]),
+        H256::default(),
+    ]);
+    assert_eq!(found.len(), 3);
+    assert_eq!(found[0], None);
+    assert_eq!(
+        rlp::decode::<BlockHeader>(found[1].as_ref().unwrap())
+            .unwrap()
+            .hash
+            .unwrap(),
+        hash_of(&headers[10])
+    );
+    assert_eq!(found[2], None);
+}
+
+#[test]
+fn finality_by_depth_and_by_difficulty() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 12);
+    let (recent, recent_hash) = (10_234_008, hash_of(&headers[6]));
+
+    // The tip is 10_234_013, 10 confirmations make 10_234_003 final.
+    assert_eq!(contract.safe_block_number(), Some(10_234_003));
+    assert!(!contract.is_finalized_block(recent, recent_hash));
+
+    // Every header adds 10 to the total difficulty, so 10_234_008 is 50 behind the tip.
+    contract.set_finality_mode(FinalityMode::Difficulty(U256(50.into())));
+    assert_eq!(contract.safe_block_number(), Some(recent));
+    assert!(contract.is_finalized_block(recent, recent_hash));
+    assert_eq!(contract.block_hash_safe(recent + 1), None);
+
+    // A reorg below the final blocks is rejected in this mode as well.
+    let fork = fake_child(&headers[4], 1001, 1);
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(fork, vec![])
+    }));
+    assert!(result.is_err());
+    assert_eq!(contract.last_block_number(), 10_234_013);
+}
+
+#[test]
+fn gc_keeps_blocks_that_are_not_final() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        3,
+        10,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    let headers = add_fake_chain(&mut contract, &first, 8);
+
+    // The tip 10_234_009 is past `finalized_gc_threshold` of 3, but no stored block has 10
+    // confirmations.
+    assert_eq!(contract.safe_block_number(), Some(10_233_999));
+    assert_eq!(contract.next_gc_target().unwrap().0, 10_234_001);
+
+    // Every header adds 10 to the total difficulty, and the final blocks follow the tip.
+    contract.set_finality_mode(FinalityMode::Difficulty(U256(50.into())));
+    assert_eq!(contract.safe_block_number(), Some(10_234_004));
+    let headers = add_fake_chain(&mut contract, &headers[7], 2);
+    assert_eq!(contract.safe_block_number(), Some(10_234_006));
+    assert_eq!(contract.next_gc_target().unwrap().0, 10_234_007);
+    assert_eq!(contract.gc_impact_of(fake_child(&headers[1], 10, 0)), 0);
+}
+
+#[test]
+fn interface_lists_methods() {
+    testing_env!(get_context(vec![], false));
+    let contract = trusted_contract(read_header(10_234_001));
+    let interface = contract.interface();
+    let mut names: Vec<&str> = interface
+        .iter()
+        .map(|method| method.split(':').next().unwrap())
+        .collect();
+
+    // Every public method of the exported impl block has to be listed.
+    let source = include_str!("lib.rs");
+    let start = source.find("#[near_bindgen]\nimpl EthClient {").unwrap();
+    let end = start + source[start..].find("\n}\n").unwrap();
+    let prefix = "    pub fn ";
+    let mut exported: Vec<&str> = source[start..end]
+        .lines()
+        .filter(|line| line.starts_with(prefix))
+        .map(|line| {
+            line[prefix.len()..]
+                .split(|c| c == '(' || c == '<')
+                .next()
+                .unwrap()
+        })
+        .collect();
+    names.sort();
+    exported.sort();
+    assert_eq!(names, exported);
+}
+
+#[test]
+fn headers_with_uncles_are_rejected_on_request() {
+    testing_env!(get_self_context());
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    contract.set_require_empty_uncles(true);
+    let parent: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    let mut child: BlockHeader = rlp::decode(&read_header(10_234_002)).unwrap();
+    assert!(contract.verify_fields(&child, &parent).is_ok());
+
+    child.uncles_hash = H256(near_keccak256(b"uncle").into());
+    assert!(contract.verify_fields(&child, &parent).is_err());
+    contract.set_require_empty_uncles(false);
+    assert!(contract.verify_fields(&child, &parent).is_ok());
+}
+
+#[test]
+fn orphans_are_reprocessed_over_several_calls() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_on_missing_parent(OnMissingParent::Buffer);
+    contract.set_max_orphan_reprocess_per_call(2);
+
+    let root = fake_child(&first, 10, 0);
+    let mut children = vec![fake_child(&root, 10, 0)];
+    for _ in 0..4 {
+        children.push(fake_child(children.last().unwrap(), 10, 0));
+    }
+    for child in children.iter().rev() {
+        contract.add_block_header(child.clone(), vec![]);
+    }
+    assert_eq!(contract.last_block_number(), 10_234_001);
+
+    contract.add_block_header(root, vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_004);
+    contract.process_pending_orphans();
+    assert_eq!(contract.last_block_number(), 10_234_006);
+    contract.process_pending_orphans();
+    assert_eq!(contract.last_block_number(), 10_234_007);
+    assert_eq!(contract.orphans.len(), 0);
+}
+
+#[test]
+#[should_panic(expected = "At least one buffered header should be recorded per call")]
+fn orphan_reprocessing_can_not_be_disabled() {
+    testing_env!(get_self_context());
+    let mut contract = trusted_contract(read_header(10_234_001));
+    contract.set_max_orphan_reprocess_per_call(0);
+}
+
+#[test]
+fn orphan_siblings_are_buffered_and_invalid_ones_skipped() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_on_missing_parent(OnMissingParent::Buffer);
+    let parent = fake_child(&first, 10, 0);
+    let light = fake_child(&parent, 10, 1);
+    let heavy = fake_child(&parent, 20, 2);
+    let mut misnumbered: BlockHeader = rlp::decode(&fake_child(&parent, 30, 3)).unwrap();
+    misnumbered.number += 1;
+    let misnumbered = rlp::encode(&misnumbered);
+
+    for orphan in vec![&light, &heavy, &misnumbered] {
+        contract.add_block_header(orphan.clone(), vec![]);
+    }
+    assert_eq!(contract.orphans.len(), 3);
+
+    contract.add_block_header(parent, vec![]);
+    assert_eq!(contract.orphans.len(), 0);
+    assert_eq!(contract.number_of(hash_of(&light)), Some(10_234_003));
+    assert_eq!(contract.block_hash(10_234_003), Some(hash_of(&heavy)));
+    assert_eq!(contract.number_of(hash_of(&misnumbered)), None);
+    assert_eq!(contract.last_block_number(), 10_234_003);
+}
+
+#[test]
+fn lowest_orphan_is_evicted() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_on_missing_parent(OnMissingParent::Buffer);
+    let mut orphans = vec![fake_child(&fake_child(&first, 10, 0), 10, 0)];
+    for _ in 0..16 {
+        orphans.push(fake_child(orphans.last().unwrap(), 10, 0));
+    }
+    for orphan in &orphans {
+        contract.add_block_header(orphan.clone(), vec![]);
+    }
+    assert_eq!(contract.orphans.len(), 16);
+    assert!(contract.orphans.get(&hash_of(&orphans[0])).is_none());
+    assert!(contract.orphans.get(&hash_of(&orphans[1])).is_some());
+}
+
+#[test]
+fn keccak256_of_empty_input() {
+    testing_env!(get_context(vec![], false));
+    let contract = trusted_contract(read_header(10_234_001));
+    assert_eq!(
+        (contract.keccak256(vec![]).0).0.to_vec(),
+        Vec::from_hex("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470").unwrap()
+    );
+}
+
+#[test]
+fn straight_chain_has_no_canonical_gaps() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 25);
+    for (i, header) in headers.iter().enumerate() {
+        assert_eq!(
+            contract.block_hash(10_234_002 + i as u64),
+            Some(hash_of(header))
+        );
+    }
+    assert_eq!(contract.block_hash(10_234_001), Some(hash_of(&first)));
+}
+
+#[test]
+fn init_and_add_makes_first_header_the_tip() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let contract =
+        EthClient::init_and_add(0, read_roots_collection().dag_merkle_roots, first.clone());
+    assert_eq!(contract.last_block_number(), 10_234_001);
+    assert_eq!(contract.block_hash(10_234_001), Some(hash_of(&first)));
+    assert!(contract.trusted_signer.is_none());
+}
+
+#[test]
+fn gc_near_the_thresholds_does_not_underflow() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 8;
+    let first = rlp::encode(&first);
+    let mut contract = trusted_contract(first.clone());
+
+    // The tip passes `finalized_gc_threshold` of 10 while staying below `hashes_gc_threshold`.
+    let headers = add_fake_chain(&mut contract, &first, 4);
+    assert_eq!(contract.last_block_number(), 12);
+    assert_eq!(contract.next_gc_target().unwrap().0, 8);
+    assert_eq!(contract.earliest_available_block(), 8);
+    assert_eq!(contract.gc_impact_of(fake_child(&headers[3], 10, 0)), 0);
+}
+
+#[test]
+fn tip_header_is_the_best_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    assert_eq!(contract.tip_header(), Some(first.clone()));
+
+    let headers = add_fake_chain(&mut contract, &first, 2);
+    let tip: BlockHeader = rlp::decode(&contract.tip_header().unwrap()).unwrap();
+    assert_eq!(tip.number, 10_234_003);
+    assert_eq!(tip.hash.unwrap(), hash_of(&headers[1]));
+}
+
+#[test]
+#[should_panic(expected = "Header 10234002 of the adopted branch is not newer than its parent")]
+fn reorg_to_branch_with_timestamp_regression_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 2);
+
+    let mut stale: BlockHeader = rlp::decode(&fake_child(&first, 9, 1)).unwrap();
+    stale.timestamp -= 1;
+    let stale = rlp::encode(&stale);
+    contract.add_block_header(stale.clone(), vec![]);
+    contract.add_block_header(fake_child(&stale, 101, 1), vec![]);
+}
+
+#[test]
+fn epochs_in_use_span_the_canonical_chain() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 10_259_998;
+    let first = rlp::encode(&first);
+    let mut contract = trusted_contract(first.clone());
+    assert_eq!(contract.epochs_in_use(), vec![341]);
+
+    add_fake_chain(&mut contract, &first, 2);
+    assert_eq!(contract.epochs_in_use(), vec![341, 342]);
+}
+
+#[test]
+fn submissions_require_the_minimal_deposit() {
+    let mut context = get_self_context();
+    context.attached_deposit = 100;
+    testing_env!(context);
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_min_submission_deposit(101);
+
+    let header = fake_child(&first, 10, 0);
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(header.clone(), vec![])
+    }));
+    assert!(result.is_err());
+    assert_eq!(contract.last_block_number(), 10_234_001);
+
+    contract.set_min_submission_deposit(100);
+    contract.add_block_header(header, vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_002);
+}
+
+#[test]
+fn deposit_of_rejected_header_is_refunded() {
+    let mut context = get_self_context();
+    context.attached_deposit = 100;
+    context.account_balance = 1000;
+    testing_env!(context);
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    contract.set_min_submission_deposit(100);
+
+    let header = fake_child(&first, 10, 0);
+    assert_eq!(
+        contract.add_block_header_result(header.clone(), vec![]),
+        ADD_HEADER_OK
+    );
+    assert_eq!(env::account_balance(), 1000);
+    assert_eq!(
+        contract.add_block_header_result(header, vec![]),
+        ADD_HEADER_KNOWN
+    );
+    assert_eq!(env::account_balance(), 900);
+}
+
+#[test]
+fn needs_dag_root_for_uncovered_epochs() {
+    testing_env!(get_context(vec![], false));
+    // The roots cover the epochs 0..512.
+    let contract = trusted_contract(read_header(10_234_001));
+    assert!(!contract.needs_dag_root_for_block(10_234_002));
+    assert!(!contract.needs_dag_root_for_block(511 * 30000 + 29999));
+    assert!(contract.needs_dag_root_for_block(512 * 30000));
+}
+
+#[test]
+fn prune_fork_removes_the_final_side_branch() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        30,
+        3,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    let main = add_fake_chain(&mut contract, &first, 2);
+    let fork_1 = fake_child(&first, 9, 1);
+    contract.add_block_header(fork_1.clone(), vec![]);
+    let fork_2 = fake_child(&fork_1, 9, 1);
+    contract.add_block_header(fork_2.clone(), vec![]);
+    add_fake_chain(&mut contract, &main[1], 4);
+    assert_eq!(contract.safe_block_number(), Some(10_234_004));
+
+    // The fork tip is a child of `fork_1`, so nothing is removed below it.
+    contract.prune_fork(hash_of(&fork_1), 10);
+    assert!(contract.infos.get(&hash_of(&fork_1)).is_some());
+    assert_eq!(contract.epoch_submission_count(341), 8);
+
+    contract.prune_fork(hash_of(&fork_2), 10);
+    assert_eq!(contract.epoch_submission_count(341), 6);
+    for header in &[fork_1, fork_2] {
+        assert!(contract.headers.get(&hash_of(header)).is_none());
+        assert!(contract.infos.get(&hash_of(header)).is_none());
+    }
+    assert_eq!(contract.known_hashes(10_234_002), vec![hash_of(&main[0])]);
+    assert_eq!(contract.known_hashes(10_234_003), vec![hash_of(&main[1])]);
+    assert!(contract.headers.get(&hash_of(&first)).is_some());
+}
+
+#[test]
+fn prune_fork_removes_emptied_numbers() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        30,
+        3,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    let main = add_fake_chain(&mut contract, &first, 1);
+    let fork = fake_child(&first, 9, 1);
+    contract.add_block_header(fork.clone(), vec![]);
+    add_fake_chain(&mut contract, &main[0], 10);
+
+    // Leave the fork header as the only one at its number.
+    contract
+        .all_header_hashes
+        .insert(&10_234_002, &vec![hash_of(&fork)]);
+    contract.prune_fork(hash_of(&fork), 1);
+    assert!(contract.all_header_hashes.get(&10_234_002).is_none());
+    assert!(contract.infos.get(&hash_of(&fork)).is_none());
+}
+
+#[test]
+fn prospective_total_difficulty_matches_recorded() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 2);
+
+    let prospective = contract
+        .prospective_total_difficulty(hash_of(&headers[1]), U256(11.into()))
+        .unwrap();
+    let child = fake_child(&headers[1], 11, 0);
+    contract.add_block_header(child.clone(), vec![]);
+    assert!(contract.confirm_total_difficulty(hash_of(&child), prospective));
+    assert_eq!(
+        contract.prospective_total_difficulty(H256::default(), U256(11.into())),
+        None
+    );
+}
+
+#[test]
+fn compact_headers_keep_roots_but_not_rlp() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    contract.set_store_full_headers(false);
+    let blocks: Vec<_> = (10_234_002..=10_234_003)
+        .map(|number| read_block(format!("./src/data/{}.json", number)))
+        .collect();
+    // The second header is verified against the compactly stored first one.
+    for block in &blocks {
+        contract.add_block_header(
+            block.header_rlp.0.clone(),
+            block.to_double_node_with_merkle_proof_vec(),
+        );
+    }
+    let second = blocks[0].header_rlp.0.clone();
+    let header: BlockHeader = rlp::decode(&second).unwrap();
+
+    assert_eq!(
+        contract.header_roots(hash_of(&second)),
+        Some((
+            header.state_root,
+            header.transactions_root,
+            header.receipts_root
+        ))
+    );
+    assert_eq!(
+        contract.header_seal(hash_of(&second)),
+        Some((header.nonce, header.mix_hash))
+    );
+    assert_eq!(contract.last_block_number(), 10_234_003);
+    assert!(contract.headers.get(&hash_of(&second)).is_none());
+    assert_eq!(contract.stats().headers_count, 3);
+    assert_eq!(contract.canonical_header(10_234_002), None);
+    assert_eq!(contract.tip_header(), None);
+    assert_eq!(contract.get_headers(vec![hash_of(&second)]), vec![None]);
+    assert_eq!(contract.header_logs_bloom(hash_of(&second)), None);
+    assert_eq!(contract.canonical_header(10_234_001), Some(first));
+}
+
+#[test]
+fn epoch_complete_follows_retained_window() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 29_998;
+    let first = rlp::encode(&first);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 3);
+    assert!(contract.epoch_complete(0));
+    assert!(contract.epoch_complete(1));
+    assert!(!contract.epoch_complete(2));
+
+    // The tip passes `hashes_gc_threshold` of 30, so the epoch 0 is no longer retained.
+    add_fake_chain(&mut contract, &headers[2], 32);
+    assert_eq!(contract.earliest_available_block(), 30_004);
+    assert!(!contract.epoch_complete(0));
+    assert!(contract.epoch_complete(1));
+}
+
+#[test]
+fn epoch_with_missing_canonical_number_is_incomplete() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 29_998;
+    let first = rlp::encode(&first);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 10);
+    assert!(contract.epoch_complete(1));
+
+    assert_eq!(contract.canonical_count_in_epoch(1), 9);
+
+    contract.canonical_header_hashes.remove(&30_004);
+    assert!(contract.epoch_complete(0));
+    assert!(!contract.epoch_complete(1));
+    assert_eq!(contract.canonical_count_in_epoch(1), 8);
+}
+
+#[test]
+#[should_panic(expected = "parent number mismatch: header 10234005, parent 10234001")]
+fn parent_number_mismatch_is_rejected() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        false,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let mut header: BlockHeader = rlp::decode(&block.header_rlp.0).unwrap();
+    header.number = 10_234_005;
+    contract.add_block_header(
+        rlp::encode(&header),
+        block.to_double_node_with_merkle_proof_vec(),
+    );
+}
+
+#[test]
+fn recent_reorgs_are_recorded_with_depth() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let main = add_fake_chain(&mut contract, &first, 3);
+    let fork_1 = fake_child(&main[0], 31, 1);
+    contract.add_block_header(fork_1.clone(), vec![]);
+    let fork_2 = fake_child(&main[0], 33, 2);
+    contract.add_block_header(fork_2.clone(), vec![]);
+
+    assert_eq!(
+        contract.recent_reorgs(10),
+        vec![
+            ReorgEvent {
+                at_block: 10_234_002,
+                old_tip: hash_of(&fork_1),
+                new_tip: hash_of(&fork_2),
+                depth: 1,
+            },
+            ReorgEvent {
+                at_block: 10_234_002,
+                old_tip: hash_of(&main[2]),
+                new_tip: hash_of(&fork_1),
+                depth: 2,
+            },
+        ]
+    );
+    assert_eq!(contract.recent_reorgs(1).len(), 1);
+}
+
+#[test]
+fn ready_for_next_epoch_requires_its_root() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let roots = read_roots_collection().dag_merkle_roots;
+    let contract = trusted_contract(first.clone());
+    assert!(contract.ready_for_next_epoch());
+
+    // The first header is in the epoch 341, so the roots end with the epoch of the best header.
+    let contract = EthClient::init(
+        true,
+        0,
+        roots[..342].to_vec(),
+        first,
+        30,
+        10,
+        10,
+        Some("bob.near".to_string()),
+        b"x".to_vec(),
+    );
+    assert!(!contract.ready_for_next_epoch());
+}
+
+#[test]
+fn initial_total_difficulty_anchors_descendants() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let baseline = U256(1_000_000_000.into());
+    // Without the baseline the first header counts only its own difficulty, so any chain
+    // claiming the real total difficulty looks heavier than the recorded one.
+    assert!(!contract.confirm_total_difficulty(hash_of(&first), baseline));
+
+    contract.set_initial_total_difficulty(baseline);
+    assert!(contract.confirm_total_difficulty(hash_of(&first), baseline));
+    let headers = add_fake_chain(&mut contract, &first, 2);
+    assert!(contract.confirm_total_difficulty(hash_of(&headers[1]), U256(1_000_000_020.into())));
+
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.set_initial_total_difficulty(baseline)
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn check_proof_ordering_detects_swapped_nodes() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234002.json".to_string());
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let mut dag_nodes = block.to_double_node_with_merkle_proof_vec();
+    assert!(contract.check_proof_ordering(block.header_rlp.0.clone(), dag_nodes.clone()));
+
+    dag_nodes.swap(10, 11);
+    assert!(!contract.check_proof_ordering(block.header_rlp.0.clone(), dag_nodes.clone()));
+    dag_nodes.pop();
+    assert!(!contract.check_proof_ordering(block.header_rlp.0.clone(), dag_nodes));
+}
+
+#[test]
+fn check_proof_ordering_rejects_malformed_header() {
+    testing_env!(get_context(vec![], false));
+    let block = read_block("./src/data/10234002.json".to_string());
+    let contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let header = block.header_rlp.0.clone();
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+
+    assert!(!contract.check_proof_ordering(header[..header.len() - 8].to_vec(), dag_nodes));
+}
+
+#[test]
+fn finality_horizon_tracks_tip() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    assert_eq!(contract.finality_horizon(), 10_233_991);
+
+    add_fake_chain(&mut contract, &first, 3);
+    assert_eq!(contract.finality_horizon(), 10_233_994);
+}
+
+#[test]
+fn verify_checkpoint_link_checks_chain_to_tip() {
+    testing_env!(get_context(vec![], false));
+    let blocks: Vec<_> = (10_234_001..=10_234_004)
+        .map(|number| read_block(format!("./src/data/{}.json", number)))
+        .collect();
+    let headers: Vec<Vec<u8>> = blocks
+        .iter()
+        .map(|block| block.header_rlp.0.clone())
+        .collect();
+    let dag_nodes: Vec<_> = blocks[1..]
+        .iter()
+        .map(|block| block.to_double_node_with_merkle_proof_vec())
+        .collect();
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        headers[0].clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    for (header, nodes) in headers[1..].iter().zip(dag_nodes.iter()) {
+        contract.add_block_header(header.clone(), nodes.clone());
+    }
+
+    assert!(contract.verify_checkpoint_link(headers.clone(), dag_nodes.clone()));
+    // The chain has to reach the tip.
+    assert!(!contract.verify_checkpoint_link(headers[..3].to_vec(), dag_nodes[..2].to_vec()));
+    // Without the second header the third one doesn't link to the first one.
+    assert!(!contract.verify_checkpoint_link(
+        vec![headers[0].clone(), headers[2].clone(), headers[3].clone()],
+        dag_nodes[1..].to_vec()
+    ));
+}
+
+#[test]
+fn verify_checkpoint_link_rejects_malformed_headers() {
+    testing_env!(get_context(vec![], false));
+    let blocks: Vec<_> = (10_234_001..=10_234_002)
+        .map(|number| read_block(format!("./src/data/{}.json", number)))
+        .collect();
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        blocks[0].header_rlp.0.clone(),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let dag_nodes = vec![blocks[1].to_double_node_with_merkle_proof_vec()];
+    contract.add_block_header(blocks[1].header_rlp.0.clone(), dag_nodes[0].clone());
+
+    let tip = blocks[1].header_rlp.0.clone();
+    assert!(!contract.verify_checkpoint_link(
+        vec![
+            blocks[0].header_rlp.0.clone(),
+            tip[..tip.len() - 1].to_vec()
+        ],
+        dag_nodes
+    ));
+}
+
+#[test]
+fn add_block_header_result_maps_rejections_to_codes() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        false,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        30,
+        3,
+        3,
+        None,
+        vec![],
+    );
+    let blocks: Vec<_> = (10_234_002..=10_234_007)
+        .map(|number| read_block(format!("./src/data/{}.json", number)))
+        .collect();
+    let add = |contract: &mut EthClient, header: Vec<u8>, block: &BlockWithProofs| {
+        contract.add_block_header_result(header, block.to_double_node_with_merkle_proof_vec())
+    };
+
+    assert_eq!(
+        add(&mut contract, blocks[1].header_rlp.0.clone(), &blocks[1]),
+        ADD_HEADER_UNKNOWN_PARENT
+    );
+    assert_eq!(
+        add(&mut contract, child_with_extra_data(&first, 33), &blocks[0]),
+        ADD_HEADER_INVALID
+    );
+    let header = &blocks[0].header_rlp.0;
+    assert_eq!(
+        add(
+            &mut contract,
+            header[..header.len() / 2].to_vec(),
+            &blocks[0]
+        ),
+        ADD_HEADER_INVALID
+    );
+    for block in &blocks {
+        assert_eq!(
+            add(&mut contract, block.header_rlp.0.clone(), block),
+            ADD_HEADER_OK
+        );
+    }
+    assert_eq!(
+        add(&mut contract, blocks[5].header_rlp.0.clone(), &blocks[5]),
+        ADD_HEADER_KNOWN
+    );
+    // The parent of a stale header is already garbage collected, so it is only reported as stale
+    // by a client buffering the orphans.
+    contract.set_on_missing_parent(OnMissingParent::Buffer);
+    assert_eq!(
+        add(
+            &mut contract,
+            child_with_extra_data(&blocks[0].header_rlp.0, 0),
+            &blocks[1]
+        ),
+        ADD_HEADER_STALE
+    );
+    assert_eq!(contract.last_block_number(), 10_234_007);
+}
+
+#[test]
+fn add_block_header_result_rejects_bad_dag_nodes() {
+    testing_env!(get_context(vec![], false));
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        read_header(10_234_001),
+        30,
+        10,
+        10,
+        None,
+        vec![],
+    );
+    let block = read_block("./src/data/10234002.json".to_string());
+    let header = block.header_rlp.0.clone();
+    let dag_nodes = block.to_double_node_with_merkle_proof_vec();
+
+    let mut swapped = dag_nodes.clone();
+    swapped.swap(0, 1);
+    let mut corrupted = dag_nodes.clone();
+    corrupted[3].proof[0] = corrupted[3].proof[1];
+    for bad_nodes in vec![swapped, corrupted, dag_nodes[..10].to_vec(), vec![]] {
+        assert_eq!(
+            contract.add_block_header_result(header.clone(), bad_nodes),
+            ADD_HEADER_INVALID
+        );
+    }
+    assert_eq!(contract.last_block_number(), 10_234_001);
+    assert_eq!(
+        contract.add_block_header_result(header, dag_nodes),
+        ADD_HEADER_OK
+    );
+    assert_eq!(contract.last_block_number(), 10_234_002);
+}
+
+#[test]
+fn header_number_difficulty_of_submitted_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let child = fake_child(&first, 13, 0);
+    contract.add_block_header(child.clone(), vec![]);
+
+    assert_eq!(
+        contract.header_number_difficulty(hash_of(&child)),
+        Some((10_234_002, U256(13.into())))
+    );
+    assert_eq!(contract.header_number_difficulty(H256::default()), None);
+}
+
+#[test]
+fn headers_older_than_min_timestamp_are_rejected() {
+    testing_env!(get_self_context());
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let cutoff = rlp::decode::<BlockHeader>(&first).unwrap().timestamp + 5;
+    contract.set_min_header_timestamp(cutoff);
+
+    let old = fake_child(&first, 11, 0);
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.add_block_header(old.clone(), vec![])
+    }));
+    assert!(result.is_err());
+    assert_eq!(contract.last_block_number(), 10_234_001);
+
+    let mut recent: BlockHeader = rlp::decode(&fake_child(&first, 11, 1)).unwrap();
+    recent.timestamp = cutoff;
+    contract.add_block_header(rlp::encode(&recent), vec![]);
+    assert_eq!(contract.last_block_number(), 10_234_002);
+}
+
+#[test]
+fn canonical_count_in_epoch_across_boundary() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 10_259_998;
+    let first = rlp::encode(&first);
+    let mut contract = trusted_contract(first.clone());
+    add_fake_chain(&mut contract, &first, 3);
+
+    // The epoch 341 ends with the block 10_259_999.
+    assert_eq!(contract.canonical_count_in_epoch(340), 0);
+    assert_eq!(contract.canonical_count_in_epoch(341), 2);
+    assert_eq!(contract.canonical_count_in_epoch(342), 2);
+    assert_eq!(contract.canonical_count_in_epoch(343), 0);
+}
+
+#[test]
+fn dag_start_epoch_is_corrected_while_paused() {
+    testing_env!(get_self_context());
+    let roots = read_roots_collection().dag_merkle_roots;
+    let first = read_header(10_234_001);
+    let mut contract = EthClient::init(
+        true,
+        300,
+        roots.clone(),
+        first.clone(),
+        30,
+        10,
+        10,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| contract.set_dag_start_epoch(0)));
+    assert!(result.is_err());
+
+    contract.pause();
+    contract.set_dag_start_epoch(0);
+    assert_eq!(contract.dag_config().0, 0);
+    assert_eq!(contract.dag_merkle_root(341), roots[341]);
+    contract.unpause();
+
+    add_fake_chain(&mut contract, &first, 1);
+    contract.pause();
+    let result = catch_unwind_silent(panic::AssertUnwindSafe(|| {
+        contract.set_dag_start_epoch(300)
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn is_retained_follows_gc() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 29);
+    assert!(contract.is_retained(10_234_001));
+    assert!(contract.is_retained(10_234_030));
+    assert!(!contract.is_retained(10_234_031));
+
+    // The tip passes `hashes_gc_threshold` of 30, so the low end of the window is removed.
+    add_fake_chain(&mut contract, &headers[28], 2);
+    assert!(!contract.is_retained(10_234_001));
+    assert!(!contract.is_retained(10_234_002));
+    assert!(contract.is_retained(10_234_003));
+    assert!(contract.is_retained(10_234_032));
+}
+
+#[test]
+fn stored_info_number_matches_stored_header() {
+    testing_env!(get_context(vec![], false));
+    let first = read_header(10_234_001);
+    let mut contract = trusted_contract(first.clone());
+    let headers = add_fake_chain(&mut contract, &first, 2);
+
+    for (i, header) in headers.iter().enumerate() {
+        let hash = hash_of(header);
+        let number = 10_234_002 + i as u64;
+        assert_eq!(contract.infos.get(&hash).unwrap().number, number);
+        assert_eq!(contract.headers.get(&hash).unwrap().number, number);
+    }
+}
+
+#[test]
+fn canonical_count_in_epoch_beyond_query_length() {
+    testing_env!(get_context(vec![], false));
+    let mut first: BlockHeader = rlp::decode(&read_header(10_234_001)).unwrap();
+    first.number = 10_258_000;
+    let first = rlp::encode(&first);
+    let mut contract = EthClient::init(
+        true,
+        0,
+        read_roots_collection().dag_merkle_roots,
+        first.clone(),
+        3000,
+        10,
+        10,
+        Some("bob.near".to_string()),
+        vec![],
+    );
+    add_fake_chain(&mut contract, &first, 1999);
+
+    assert_eq!(contract.canonical_count_in_epoch(341), 2000);
+    assert!(contract.epoch_complete(341));
+}
@@ -39,7 +39,8 @@ const borshSchema = {
       ['hashes_gc_threshold', 'u64'],
       ['finalized_gc_threshold', 'u64'],
       ['num_confirmations', 'u64'],
-      ['trusted_signer', '?AccountId']
+      ['trusted_signer', '?AccountId'],
+      ['prefix', ['u8']]
     ]
   },
   dagMerkleRootInput: {
@@ -161,7 +162,8 @@ class EthOnNearClientContract extends BorshContract {
           hashes_gc_threshold: 40000,
           finalized_gc_threshold: 500,
           num_confirmations: 10,
-          trusted_signer: trustedSigner
+          trusted_signer: trustedSigner,
+          prefix: []
         },
         new BN('300000000000000')
       )